@@ -0,0 +1,171 @@
+//! Authenticates workloads calling the HTTP API by their ServiceAccount tokens, and authorizes
+//! them against RBAC, so that access to connections is granted like access to any other resource.
+
+use crate::{oauth_connection::OAuthConnection, ApplicationState, Error, Result};
+use axum::http::{header, HeaderMap};
+use k8s_openapi::api::{
+    authentication::v1::{TokenReview, TokenReviewSpec},
+    authorization::v1::{ResourceAttributes, SubjectAccessReview, SubjectAccessReviewSpec},
+};
+use kube::{
+    api::{Api, PostParams},
+    ResourceExt,
+};
+use std::collections::BTreeMap;
+
+/// The Kubernetes user behind a request, as authenticated by the API server
+#[derive(Clone, Debug)]
+pub struct Caller {
+    pub username: String,
+    pub uid: Option<String>,
+    pub groups: Vec<String>,
+    pub extra: BTreeMap<String, Vec<String>>,
+}
+
+impl Caller {
+    /// The namespace and name of the ServiceAccount the caller authenticated as, if any
+    pub fn service_account(&self) -> Option<(&str, &str)> {
+        self.username
+            .strip_prefix("system:serviceaccount:")
+            .and_then(|service_account| service_account.split_once(':'))
+    }
+}
+
+/// Authenticates the bearer token of a request with a `TokenReview`. Tokens must have been issued
+/// for the configured audience, which the review must report, so that tokens meant for other
+/// services can't be replayed here.
+pub async fn authenticate(state: &ApplicationState, headers: &HeaderMap) -> Result<Caller> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(bearer_token)
+        .ok_or_else(|| Error::Unauthenticated(String::from("A bearer token is required")))?;
+
+    let review = TokenReview {
+        spec: TokenReviewSpec {
+            audiences: Some(vec![state.config.token_audience.clone()]),
+            token: Some(token.to_string()),
+        },
+        ..TokenReview::default()
+    };
+
+    let status = Api::<TokenReview>::all(state.client.clone())
+        .create(&PostParams::default(), &review)
+        .await?
+        .status
+        .unwrap_or_default();
+
+    if status.authenticated != Some(true) {
+        return Err(Error::Unauthenticated(
+            status
+                .error
+                .unwrap_or_else(|| String::from("Token was not accepted")),
+        ));
+    }
+
+    // Authenticators which don't support audiences may accept a token regardless, so the API
+    // server only vouches for the audiences it reports
+    if !status
+        .audiences
+        .unwrap_or_default()
+        .contains(&state.config.token_audience)
+    {
+        return Err(Error::Unauthenticated(format!(
+            "Token was not issued for the {} audience",
+            state.config.token_audience
+        )));
+    }
+
+    let user = status.user.unwrap_or_default();
+
+    Ok(Caller {
+        username: user.username.unwrap_or_default(),
+        uid: user.uid,
+        groups: user.groups.unwrap_or_default(),
+        extra: user.extra.unwrap_or_default(),
+    })
+}
+
+/// The credentials of a `Bearer` authorization, whose scheme is case insensitive
+fn bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.split_once(' ')?;
+
+    match scheme.eq_ignore_ascii_case("bearer") && !token.trim().is_empty() {
+        true => Some(token.trim()),
+        false => None,
+    }
+}
+
+/// Checks the connection's access policy permits the caller, then with a `SubjectAccessReview`
+/// that the caller may `verb` the `token` subresource of the connection, which RBAC can grant
+/// although the API server doesn't serve it. Using the token is `get`, and revoking it `delete`.
+pub async fn authorize(
+    state: &ApplicationState,
+    caller: &Caller,
+    oauth_connection: &OAuthConnection,
+    verb: &str,
+) -> Result<()> {
+    if !oauth_connection.permits(caller) {
+        return Err(Error::Forbidden(format!(
+            "{} is not permitted by the access policy of OAuthConnection {}",
+            caller.username,
+            oauth_connection.name()
+        )));
+    }
+
+    let review = SubjectAccessReview {
+        spec: SubjectAccessReviewSpec {
+            user: Some(caller.username.clone()),
+            uid: caller.uid.clone(),
+            groups: Some(caller.groups.clone()),
+            extra: Some(caller.extra.clone()),
+            resource_attributes: Some(ResourceAttributes {
+                group: Some(String::from("chappaai.dev")),
+                resource: Some(String::from("oauthconnections")),
+                subresource: Some(String::from("token")),
+                verb: Some(verb.to_string()),
+                namespace: oauth_connection.namespace(),
+                name: Some(oauth_connection.name()),
+                ..ResourceAttributes::default()
+            }),
+            ..SubjectAccessReviewSpec::default()
+        },
+        ..SubjectAccessReview::default()
+    };
+
+    let status = Api::<SubjectAccessReview>::all(state.client.clone())
+        .create(&PostParams::default(), &review)
+        .await?
+        .status
+        .unwrap_or_default();
+
+    match status.allowed {
+        true => Ok(()),
+        false => Err(Error::Forbidden(format!(
+            "{} may not {} the token of OAuthConnection {}",
+            caller.username,
+            verb,
+            oauth_connection.name()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_bearer_tokens_whatever_the_schemes_case() {
+        assert_eq!(bearer_token("Bearer abc"), Some("abc"));
+        assert_eq!(bearer_token("bearer abc"), Some("abc"));
+        assert_eq!(bearer_token("BEARER  abc "), Some("abc"));
+    }
+
+    #[test]
+    fn rejects_other_schemes_and_empty_tokens() {
+        assert_eq!(bearer_token("Basic YWJjOmRlZg=="), None);
+        assert_eq!(bearer_token("Bearer "), None);
+        assert_eq!(bearer_token("Bearer"), None);
+        assert_eq!(bearer_token("Bearerabc"), None);
+    }
+}
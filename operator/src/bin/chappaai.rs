@@ -1,51 +1,203 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::sync::Arc;
 
-use axum::{routing::get, Extension, Router};
+use axum::{
+    middleware,
+    routing::{any, get, post},
+    Extension, Router,
+};
 use chappaai::{
+    config::Config,
+    health,
+    kubernetes::leader::{LeaderElection, Leadership},
+    metrics::{self, Metrics},
     oauth_api::{self},
-    oauth_connection::{self},
-    ApplicationState, Result,
+    oauth_connection::{self, PendingAuthorizations},
+    schema, shutdown, telemetry, ui, webhook, ApplicationState, Error, Result,
 };
 
-use tracing::log::warn;
-use tracing_subscriber::{prelude::*, EnvFilter, Registry};
+use futures::FutureExt;
+use tracing::log::{info, warn};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let logger = tracing_subscriber::fmt::layer().json();
-    let env_filter = EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new("info"))?;
+    let config = Arc::new(Config::load()?);
 
-    let collector = Registry::default().with(logger).with(env_filter);
-    tracing::subscriber::set_global_default(collector)?;
+    telemetry::init()?;
 
     let client = kube::Client::try_default().await?;
+    let metrics = Arc::new(Metrics::default());
+
+    let (shutdown_trigger, shutdown) = shutdown::channel();
+
+    // Only the leader runs controllers, allowing multiple replicas to serve the HTTP API
+    let election = config.leader_election.then(|| {
+        let identity = std::env::var("POD_NAME")
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .unwrap_or_else(|_| String::from("chappaai"));
+
+        LeaderElection::new(client.clone(), &config.lease_name, &identity)
+    });
 
-    let (_, oauth_api_store, oauth_api_controller) = oauth_api::Manager::new(client.clone()).await;
-    let (_, oauth_connection_store, oauth_connection_controller) =
-        crate::oauth_connection::Manager::new(client.clone()).await;
+    let (leadership, leader_election) = match &election {
+        None => (Leadership::always(), futures::future::pending().boxed()),
+        Some(election) => {
+            let (leadership, leader_election) = election.clone().run();
+            (leadership, leader_election.boxed())
+        }
+    };
 
-    let address = SocketAddr::from(([0, 0, 0, 0], 4640));
+    // Spawned, so that the lease is still renewed while reconciliations drain during shutdown
+    let mut leader_election = tokio::spawn(leader_election);
+
+    let (oauth_api_manager, oauth_api_store, oauth_api_controller) = oauth_api::Manager::new(
+        client.clone(),
+        config.clone(),
+        metrics.clone(),
+        leadership.clone(),
+        shutdown.clone(),
+    )
+    .await;
+    let (oauth_connection_manager, oauth_connection_store, oauth_connection_controller) =
+        crate::oauth_connection::Manager::new(
+            client.clone(),
+            config.clone(),
+            metrics.clone(),
+            leadership.clone(),
+            shutdown.clone(),
+        )
+        .await;
+
+    let address = config.bind_address;
+
+    let authorizations = PendingAuthorizations::load_or_create(client.clone(), &config).await?;
+
+    // Bounded, so that an unresponsive API can't hold requests, and the drain on shutdown, open
+    let http = reqwest::Client::builder()
+        .connect_timeout(config.http_connect_timeout())
+        .timeout(config.http_timeout())
+        .build()
+        .map_err(|e| Error::ConfigError(format!("Failed to build the HTTP client: {}", e)))?;
 
     let application_state = Arc::new(ApplicationState {
         client,
         oauth_apis: oauth_api_store,
         oauth_connections: oauth_connection_store,
+        authorizations,
+        refreshes: Default::default(),
+        http,
+        reporter: "chappaai-api".into(),
+        metrics,
+        oauth_api_manager,
+        oauth_connection_manager,
+        leadership,
+        shutdown: shutdown.clone(),
+        config: config.clone(),
     });
 
     let router = Router::new()
+        .route_service("/oauth/schema", get(schema::get))
         .route_service("/oauth/apis", get(oauth_api::api::list))
+        .route_service("/oauth/apis/:name", get(oauth_api::api::get))
         .route_service("/oauth/connections", get(oauth_connection::api::list))
-        .route_service("/oauth/connections/:name", get(oauth_connection::api::connect))
+        .route_service("/oauth/connections/:name", get(oauth_connection::api::get))
+        .route_service(
+            "/oauth/connections/:name/connect",
+            get(oauth_connection::api::connect),
+        )
+        .route_service(
+            "/oauth/connections/:name/token",
+            get(oauth_connection::api::token),
+        )
+        .route_service(
+            "/oauth/connections/:name/disconnect",
+            post(oauth_connection::api::disconnect),
+        )
         .route_service("/oauth/callback/:name", get(oauth_connection::api::callback))
-        .layer(Extension(application_state));
+        .route_service("/proxy/:connection/*path", any(oauth_connection::proxy::proxy));
+
+    // The embedded UI lets small clusters run without the separate web container
+    let router = match config.embedded_ui {
+        true => router.merge(ui::router()),
+        false => router,
+    };
 
-    let api = axum::Server::bind(&address).serve(router.into_make_service());
+    let router = router
+        .route_service("/metrics", get(metrics::handler))
+        .route_service("/healthz", get(health::healthz))
+        .route_service("/readyz", get(health::readyz))
+        .layer(middleware::from_fn(metrics::track_http))
+        .layer(middleware::from_fn(telemetry::trace_http))
+        .layer(Extension(application_state.clone()));
 
-    tokio::select! {
-        _ = oauth_api_controller => warn!("OAuth API controller exited"),
-        _ = oauth_connection_controller => warn!("OAuth Connection controller exited"),
-        _ = api => warn!("API server exited"),
+    // Without the webhook, stand in a future which finishes on shutdown, for draining
+    let webhook = match config.webhook {
+        true => webhook::serve(application_state, shutdown.clone())
+            .map(|result| {
+                if let Err(error) = result {
+                    warn!("Admission webhook failed: {:?}", error);
+                }
+            })
+            .boxed()
+            .shared(),
+        false => shutdown.clone().boxed().shared(),
+    };
+
+    // Once shutdown begins, the server stops accepting connections and drains those in flight
+    let api = axum::Server::bind(&address)
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(shutdown.clone())
+        .map(|result| {
+            if let Err(error) = result {
+                warn!("API server failed: {:?}", error);
+            }
+        })
+        .boxed()
+        .shared();
+
+    let oauth_api_controller = oauth_api_controller.shared();
+    let oauth_connection_controller = oauth_connection_controller.shared();
+
+    let result = tokio::select! {
+        _ = shutdown::signal() => Ok(()),
+        _ = oauth_api_controller.clone() => Err(Error::GenericError(String::from("OAuth API controller exited"))),
+        _ = oauth_connection_controller.clone() => {
+            Err(Error::GenericError(String::from("OAuth Connection controller exited")))
+        }
+        _ = api.clone() => Err(Error::GenericError(String::from("API server exited"))),
+        _ = webhook.clone() => Err(Error::GenericError(String::from("Admission webhook exited"))),
+        _ = &mut leader_election => Err(Error::GenericError(String::from("Leader election exited"))),
+    };
+
+    if let Err(error) = &result {
+        warn!("Shutting down unexpectedly: {}", error);
+    }
+
+    info!("Draining in-flight requests and reconciliations");
+    shutdown_trigger.trigger();
+
+    let drained = futures::future::join4(api, webhook, oauth_api_controller, oauth_connection_controller);
+    if tokio::time::timeout(config.shutdown_timeout(), drained)
+        .await
+        .is_err()
+    {
+        warn!(
+            "Timed out after {}s draining requests and reconciliations",
+            config.shutdown_timeout_seconds
+        );
     }
 
-    Ok(())
+    // Only once the controllers have stopped is the lease given up, sparing the next leader waiting
+    // out the lease
+    leader_election.abort();
+    let _ = leader_election.await;
+    if let Some(election) = election {
+        if let Err(error) = election.release().await {
+            warn!("Failed to release the lease: {:?}", error);
+        }
+    }
+
+    // Export the spans of the reconciliations and requests just drained
+    let _ = tokio::task::spawn_blocking(telemetry::shutdown).await;
+
+    result
 }
@@ -1,7 +1,35 @@
-pub(crate) use chappaai::{oauth_api::OAuthApi, oauth_connection::OAuthConnection};
+pub(crate) use chappaai::{
+    oauth_api::{self, OAuthApi},
+    oauth_connection::{self, OAuthConnection},
+};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
 pub(crate) use kube::CustomResourceExt;
 
 fn main() {
-    print!("{}", serde_yaml::to_string(&OAuthApi::crd()).unwrap());
-    print!("{}", serde_yaml::to_string(&OAuthConnection::crd()).unwrap());
+    print!(
+        "{}",
+        serde_yaml::to_string(&unserved(OAuthApi::crd(), oauth_api::v2::OAuthApi::crd())).unwrap()
+    );
+    print!(
+        "{}",
+        serde_yaml::to_string(&unserved(
+            OAuthConnection::crd(),
+            oauth_connection::v2::OAuthConnection::crd()
+        ))
+        .unwrap()
+    );
+}
+
+/// Adds the versions of `next` to those of `crd`, which remains the stored version. They're left unserved,
+/// as only the conversion webhook can convert them, and it serves them when it registers itself.
+fn unserved(mut crd: CustomResourceDefinition, next: CustomResourceDefinition) -> CustomResourceDefinition {
+    crd.spec
+        .versions
+        .extend(next.spec.versions.into_iter().map(|mut version| {
+            version.served = false;
+            version.storage = false;
+            version
+        }));
+
+    crd
 }
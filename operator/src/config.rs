@@ -0,0 +1,421 @@
+use crate::{Error, Result};
+use clap::Parser;
+use serde::Deserialize;
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+/// Command line arguments, each of which may also be set from the environment. Anything not set
+/// here falls back to the YAML configuration file, then to the defaults.
+#[derive(Parser, Debug, Default)]
+#[command(name = "chappaai", version, about = "The Kubernetes OAuth Operator")]
+pub struct Args {
+    /// Path to a YAML configuration file
+    #[arg(long, env = "CHAPPAAI_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address the HTTP API listens on
+    #[arg(long, env = "CHAPPAAI_BIND_ADDRESS")]
+    pub bind_address: Option<SocketAddr>,
+
+    /// Seconds between reconciliations of a healthy resource
+    #[arg(long, env = "CHAPPAAI_REQUEUE_SECONDS")]
+    pub requeue_seconds: Option<u64>,
+
+    /// Seconds before the first retry of a failed reconciliation, doubling with each failure
+    #[arg(long, env = "CHAPPAAI_ERROR_BACKOFF_SECONDS")]
+    pub error_backoff_seconds: Option<u64>,
+
+    /// Upper bound, in seconds, of the delay between retries of a failed reconciliation
+    #[arg(long, env = "CHAPPAAI_ERROR_BACKOFF_MAX_SECONDS")]
+    pub error_backoff_max_seconds: Option<u64>,
+
+    /// Field manager used for server-side apply
+    #[arg(long, env = "CHAPPAAI_FIELD_MANAGER")]
+    pub field_manager: Option<String>,
+
+    /// Prefix of the Secrets tokens are stored in, followed by the OAuthConnection name
+    #[arg(long, env = "CHAPPAAI_SECRET_PREFIX")]
+    pub secret_prefix: Option<String>,
+
+    /// Serve the embedded connection UI
+    #[arg(long, env = "CHAPPAAI_EMBEDDED_UI")]
+    pub embedded_ui: Option<bool>,
+
+    /// Elect a leader, so that only one replica runs the controllers
+    #[arg(long, env = "CHAPPAAI_LEADER_ELECTION")]
+    pub leader_election: Option<bool>,
+
+    /// Name of the Lease used for leader election
+    #[arg(long, env = "CHAPPAAI_LEASE_NAME")]
+    pub lease_name: Option<String>,
+
+    /// Seconds to drain in-flight requests and reconciliations on shutdown
+    #[arg(long, env = "CHAPPAAI_SHUTDOWN_TIMEOUT_SECONDS")]
+    pub shutdown_timeout_seconds: Option<u64>,
+
+    /// Serve the validating admission webhook and register it with the API server
+    #[arg(long, env = "CHAPPAAI_WEBHOOK")]
+    pub webhook: Option<bool>,
+
+    /// Address the admission webhook listens on, over HTTPS
+    #[arg(long, env = "CHAPPAAI_WEBHOOK_BIND_ADDRESS")]
+    pub webhook_bind_address: Option<SocketAddr>,
+
+    /// Name of the Service, in the operator's namespace, the API server calls the webhook through
+    #[arg(long, env = "CHAPPAAI_WEBHOOK_SERVICE")]
+    pub webhook_service: Option<String>,
+
+    /// Name of the Secret holding the webhook's self-signed certificate
+    #[arg(long, env = "CHAPPAAI_WEBHOOK_SECRET")]
+    pub webhook_secret: Option<String>,
+
+    /// Name of the Secret, in the operator's namespace, holding the key which seals the OAuth state
+    /// sent to providers, so that any replica can complete an authorization. Generated if missing.
+    #[arg(long, env = "CHAPPAAI_STATE_SECRET")]
+    pub state_secret: Option<String>,
+
+    /// Audience ServiceAccount tokens must be issued for to request connection tokens
+    #[arg(long, env = "CHAPPAAI_TOKEN_AUDIENCE")]
+    pub token_audience: Option<String>,
+
+    /// Name of the Secret, in the operator's namespace, holding the AES-256 keys that encrypt
+    /// tokens stored in an EncryptedSecret, keyed by key ID
+    #[arg(long, env = "CHAPPAAI_ENCRYPTION_SECRET")]
+    pub encryption_secret: Option<String>,
+
+    /// ID of the key in the encryption Secret that tokens are encrypted with, the others only
+    /// decrypting tokens until they are re-encrypted
+    #[arg(long, env = "CHAPPAAI_ENCRYPTION_KEY_ID")]
+    pub encryption_key_id: Option<String>,
+
+    /// Directory tokens are written beneath, for OAuthConnections storing them in a File
+    #[arg(long, env = "CHAPPAAI_TOKEN_DIRECTORY")]
+    pub token_directory: Option<PathBuf>,
+
+    /// Largest request body, in bytes, the proxy forwards
+    #[arg(long, env = "CHAPPAAI_PROXY_BODY_LIMIT_BYTES")]
+    pub proxy_body_limit_bytes: Option<usize>,
+
+    /// Seconds to wait for a connection to an API, for proxied requests and identity lookups
+    #[arg(long, env = "CHAPPAAI_HTTP_CONNECT_TIMEOUT_SECONDS")]
+    pub http_connect_timeout_seconds: Option<u64>,
+
+    /// Seconds to wait for a whole request to an API, for proxied requests and identity lookups
+    #[arg(long, env = "CHAPPAAI_HTTP_TIMEOUT_SECONDS")]
+    pub http_timeout_seconds: Option<u64>,
+}
+
+/// Operator configuration, validated at startup
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct Config {
+    pub bind_address: SocketAddr,
+    pub requeue_seconds: u64,
+    pub error_backoff_seconds: u64,
+    pub error_backoff_max_seconds: u64,
+    pub field_manager: String,
+    pub secret_prefix: String,
+    pub embedded_ui: bool,
+    pub leader_election: bool,
+    pub lease_name: String,
+    pub shutdown_timeout_seconds: u64,
+    pub webhook: bool,
+    pub webhook_bind_address: SocketAddr,
+    pub webhook_service: String,
+    pub webhook_secret: String,
+    pub state_secret: String,
+    pub token_audience: String,
+    pub encryption_secret: Option<String>,
+    pub encryption_key_id: Option<String>,
+    pub token_directory: PathBuf,
+    pub proxy_body_limit_bytes: usize,
+    pub http_connect_timeout_seconds: u64,
+    pub http_timeout_seconds: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_address: SocketAddr::from(([0, 0, 0, 0], 4640)),
+            requeue_seconds: 60,
+            error_backoff_seconds: 5,
+            error_backoff_max_seconds: 5 * 60,
+            field_manager: String::from("chappaai"),
+            secret_prefix: String::from("chappaai-"),
+            embedded_ui: false,
+            leader_election: true,
+            lease_name: String::from("chappaai"),
+            shutdown_timeout_seconds: 25,
+            webhook: false,
+            webhook_bind_address: SocketAddr::from(([0, 0, 0, 0], 8443)),
+            webhook_service: String::from("chappaai"),
+            webhook_secret: String::from("chappaai-webhook-tls"),
+            state_secret: String::from("chappaai-state"),
+            token_audience: String::from("chappaai"),
+            encryption_secret: None,
+            encryption_key_id: None,
+            token_directory: PathBuf::from("/var/lib/chappaai/tokens"),
+            proxy_body_limit_bytes: 10 * 1024 * 1024,
+            http_connect_timeout_seconds: 10,
+            http_timeout_seconds: 30,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration from the command line, environment and configuration file
+    pub fn load() -> Result<Self> {
+        Config::from_args(Args::parse())
+    }
+
+    pub fn from_args(args: Args) -> Result<Self> {
+        let config = match &args.config {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .map_err(|e| Error::ConfigError(format!("Failed to read {}: {}", path.display(), e)))?;
+
+                serde_yaml::from_str(&contents)
+                    .map_err(|e| Error::ConfigError(format!("Failed to parse {}: {}", path.display(), e)))?
+            }
+            None => Config::default(),
+        };
+
+        let config = Config {
+            bind_address: args.bind_address.unwrap_or(config.bind_address),
+            requeue_seconds: args.requeue_seconds.unwrap_or(config.requeue_seconds),
+            error_backoff_seconds: args.error_backoff_seconds.unwrap_or(config.error_backoff_seconds),
+            error_backoff_max_seconds: args
+                .error_backoff_max_seconds
+                .unwrap_or(config.error_backoff_max_seconds),
+            field_manager: args.field_manager.unwrap_or(config.field_manager),
+            secret_prefix: args.secret_prefix.unwrap_or(config.secret_prefix),
+            embedded_ui: args.embedded_ui.unwrap_or(config.embedded_ui),
+            leader_election: args.leader_election.unwrap_or(config.leader_election),
+            lease_name: args.lease_name.unwrap_or(config.lease_name),
+            shutdown_timeout_seconds: args
+                .shutdown_timeout_seconds
+                .unwrap_or(config.shutdown_timeout_seconds),
+            webhook: args.webhook.unwrap_or(config.webhook),
+            webhook_bind_address: args.webhook_bind_address.unwrap_or(config.webhook_bind_address),
+            webhook_service: args.webhook_service.unwrap_or(config.webhook_service),
+            webhook_secret: args.webhook_secret.unwrap_or(config.webhook_secret),
+            state_secret: args.state_secret.unwrap_or(config.state_secret),
+            token_audience: args.token_audience.unwrap_or(config.token_audience),
+            encryption_secret: args.encryption_secret.or(config.encryption_secret),
+            encryption_key_id: args.encryption_key_id.or(config.encryption_key_id),
+            token_directory: args.token_directory.unwrap_or(config.token_directory),
+            proxy_body_limit_bytes: args
+                .proxy_body_limit_bytes
+                .unwrap_or(config.proxy_body_limit_bytes),
+            http_connect_timeout_seconds: args
+                .http_connect_timeout_seconds
+                .unwrap_or(config.http_connect_timeout_seconds),
+            http_timeout_seconds: args.http_timeout_seconds.unwrap_or(config.http_timeout_seconds),
+        };
+
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.requeue_seconds == 0 {
+            return Err(Error::ConfigError(String::from(
+                "requeueSeconds must be greater than 0",
+            )));
+        }
+
+        if self.error_backoff_seconds == 0 {
+            return Err(Error::ConfigError(String::from(
+                "errorBackoffSeconds must be greater than 0",
+            )));
+        }
+
+        if self.error_backoff_max_seconds < self.error_backoff_seconds {
+            return Err(Error::ConfigError(String::from(
+                "errorBackoffMaxSeconds must not be less than errorBackoffSeconds",
+            )));
+        }
+
+        // Refreshes write with the field manager suffixed by `-refresh`, within the limit of 128
+        if self.field_manager.is_empty() || self.field_manager.len() > 120 {
+            return Err(Error::ConfigError(String::from(
+                "fieldManager must be between 1 and 120 characters",
+            )));
+        }
+
+        // The prefix and an OAuthConnection name must together form a valid Secret name
+        if !self
+            .secret_prefix
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.')
+            || self.secret_prefix.starts_with(['-', '.'])
+        {
+            return Err(Error::ConfigError(format!(
+                "secretPrefix {:?} is not a valid DNS subdomain prefix",
+                self.secret_prefix
+            )));
+        }
+
+        if self.leader_election && self.lease_name.is_empty() {
+            return Err(Error::ConfigError(String::from(
+                "leaseName is required for leader election",
+            )));
+        }
+
+        if self.webhook && (self.webhook_service.is_empty() || self.webhook_secret.is_empty()) {
+            return Err(Error::ConfigError(String::from(
+                "webhookService and webhookSecret are required for the webhook",
+            )));
+        }
+
+        if self.state_secret.is_empty() {
+            return Err(Error::ConfigError(String::from("stateSecret must not be empty")));
+        }
+
+        if self.token_audience.is_empty() {
+            return Err(Error::ConfigError(String::from(
+                "tokenAudience must not be empty",
+            )));
+        }
+
+        if self.encryption_secret.is_some() != self.encryption_key_id.is_some() {
+            return Err(Error::ConfigError(String::from(
+                "encryptionSecret and encryptionKeyId must be set together",
+            )));
+        }
+
+        if !self.token_directory.is_absolute() {
+            return Err(Error::ConfigError(String::from(
+                "tokenDirectory must be an absolute path",
+            )));
+        }
+
+        if self.http_connect_timeout_seconds == 0 || self.http_timeout_seconds == 0 {
+            return Err(Error::ConfigError(String::from(
+                "httpConnectTimeoutSeconds and httpTimeoutSeconds must be greater than 0",
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn requeue(&self) -> Duration {
+        Duration::from_secs(self.requeue_seconds)
+    }
+
+    pub fn error_backoff(&self) -> Duration {
+        Duration::from_secs(self.error_backoff_seconds)
+    }
+
+    pub fn error_backoff_max(&self) -> Duration {
+        Duration::from_secs(self.error_backoff_max_seconds)
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.shutdown_timeout_seconds)
+    }
+
+    pub fn http_connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.http_connect_timeout_seconds)
+    }
+
+    pub fn http_timeout(&self) -> Duration {
+        Duration::from_secs(self.http_timeout_seconds)
+    }
+
+    /// Name of the Secret holding the token of an OAuthConnection
+    pub fn secret_name(&self, oauth_connection: &str) -> String {
+        format!("{}{}", self.secret_prefix, oauth_connection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_the_defaults() {
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn prefers_arguments_to_the_defaults() {
+        let config = Config::from_args(Args {
+            requeue_seconds: Some(30),
+            secret_prefix: Some(String::from("oauth-")),
+            ..Args::default()
+        })
+        .unwrap();
+
+        assert_eq!(config.requeue(), Duration::from_secs(30));
+        assert_eq!(config.secret_name("github"), "oauth-github");
+        assert_eq!(config.field_manager, "chappaai");
+    }
+
+    #[test]
+    fn rejects_invalid_configurations() {
+        let invalid = [
+            Config {
+                requeue_seconds: 0,
+                ..Config::default()
+            },
+            Config {
+                error_backoff_seconds: 10,
+                error_backoff_max_seconds: 5,
+                ..Config::default()
+            },
+            Config {
+                field_manager: "f".repeat(121),
+                ..Config::default()
+            },
+            Config {
+                secret_prefix: String::from("Chappaai_"),
+                ..Config::default()
+            },
+            Config {
+                secret_prefix: String::from("-chappaai"),
+                ..Config::default()
+            },
+            Config {
+                lease_name: String::new(),
+                ..Config::default()
+            },
+            Config {
+                webhook: true,
+                webhook_secret: String::new(),
+                ..Config::default()
+            },
+            Config {
+                encryption_secret: Some(String::from("chappaai-encryption")),
+                ..Config::default()
+            },
+            Config {
+                token_directory: PathBuf::from("tokens"),
+                ..Config::default()
+            },
+            Config {
+                http_timeout_seconds: 0,
+                ..Config::default()
+            },
+        ];
+
+        for config in invalid {
+            assert!(
+                matches!(config.validate(), Err(Error::ConfigError(_))),
+                "{:?}",
+                config
+            );
+        }
+    }
+
+    #[test]
+    fn allows_no_lease_name_without_leader_election() {
+        let config = Config {
+            leader_election: false,
+            lease_name: String::new(),
+            ..Config::default()
+        };
+
+        assert!(config.validate().is_ok());
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{kubernetes::controller, ApplicationState};
+
+use axum::{response::IntoResponse, Extension, Json};
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
+use hyper::StatusCode;
+use serde::Serialize;
+use std::{sync::Arc, time::Duration};
+
+/// How long `/readyz` waits for the Kubernetes API server before reporting it unreachable
+const KUBERNETES_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ControllerHealth {
+    name: String,
+    synced: bool,
+    last_reconciled: DateTime<Utc>,
+}
+
+impl ControllerHealth {
+    fn new(name: &str, synced: bool, state: controller::State) -> Self {
+        ControllerHealth {
+            name: name.to_string(),
+            synced,
+            last_reconciled: state.last_event,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KubernetesHealth {
+    reachable: bool,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Health {
+    ready: bool,
+    shutting_down: bool,
+    leader: bool,
+    controllers: Vec<ControllerHealth>,
+    kubernetes: Option<KubernetesHealth>,
+}
+
+async fn controllers(state: &ApplicationState) -> Vec<ControllerHealth> {
+    vec![
+        ControllerHealth::new(
+            "oauth-apis",
+            state.oauth_api_manager.synced(),
+            state.oauth_api_manager.state().await,
+        ),
+        ControllerHealth::new(
+            "oauth-connections",
+            state.oauth_connection_manager.synced(),
+            state.oauth_connection_manager.state().await,
+        ),
+    ]
+}
+
+/// Liveness: the process is serving requests. Reports controller state without judging it.
+pub async fn healthz(Extension(state): Extension<Arc<ApplicationState>>) -> impl IntoResponse {
+    Json(Health {
+        ready: true,
+        shutting_down: shutting_down(&state),
+        leader: state.leadership.is_leader(),
+        controllers: controllers(&state).await,
+        kubernetes: None,
+    })
+}
+
+fn shutting_down(state: &ApplicationState) -> bool {
+    state.shutdown.clone().now_or_never().is_some()
+}
+
+/// Readiness: not shutting down, both reflector stores have synced and the Kubernetes API server is reachable
+pub async fn readyz(Extension(state): Extension<Arc<ApplicationState>>) -> impl IntoResponse {
+    let controllers = controllers(&state).await;
+    let shutting_down = shutting_down(&state);
+
+    let kubernetes = match tokio::time::timeout(KUBERNETES_TIMEOUT, state.client.apiserver_version()).await {
+        Ok(Ok(_)) => KubernetesHealth {
+            reachable: true,
+            error: None,
+        },
+        Ok(Err(error)) => KubernetesHealth {
+            reachable: false,
+            error: Some(error.to_string()),
+        },
+        Err(_) => KubernetesHealth {
+            reachable: false,
+            error: Some(String::from("Timed out")),
+        },
+    };
+
+    let ready =
+        !shutting_down && kubernetes.reachable && controllers.iter().all(|controller| controller.synced);
+    let status = match ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (
+        status,
+        Json(Health {
+            ready,
+            shutting_down,
+            leader: state.leadership.is_leader(),
+            controllers,
+            kubernetes: Some(kubernetes),
+        }),
+    )
+}
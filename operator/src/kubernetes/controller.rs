@@ -1,8 +1,23 @@
+use crate::{config::Config, metrics::Metrics, telemetry, Error};
 use chrono::prelude::*;
-use kube::{client::Client, runtime::events::Reporter};
+use futures::Future;
+use kube::{
+    client::Client,
+    runtime::{
+        controller::{self, Action},
+        events::Reporter,
+    },
+    Resource, ResourceExt,
+};
+use rand::Rng;
 use serde::Serialize;
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tokio::sync::RwLock;
+use tracing::{field, info_span, warn, Instrument};
 
 #[derive(Clone, Serialize)]
 pub struct State {
@@ -26,4 +41,212 @@ impl State {
 pub struct Data {
     pub client: Client,
     pub state: Arc<RwLock<State>>,
+    pub metrics: Arc<Metrics>,
+    pub config: Arc<Config>,
+    pub backoff: Arc<Backoff>,
+}
+
+impl Data {
+    pub fn new(
+        client: Client,
+        state: Arc<RwLock<State>>,
+        metrics: Arc<Metrics>,
+        config: Arc<Config>,
+    ) -> Self {
+        let backoff = Arc::new(Backoff::new(config.error_backoff(), config.error_backoff_max()));
+
+        Data {
+            client,
+            state,
+            metrics,
+            config,
+            backoff,
+        }
+    }
+
+    /// Runs `reconciliation` of `object` within a span carrying its trace ID, timing it and deciding
+    /// what follows through `requeue`
+    pub async fn reconcile<K, F>(
+        &self,
+        controller: &str,
+        object: &K,
+        reconciliation: F,
+    ) -> Result<Action, Error>
+    where
+        K: Resource,
+        F: Future<Output = Result<Action, Error>>,
+    {
+        let key = object_key(object.namespace(), &object.name());
+        let span = info_span!("reconciliation", controller, object = %key, trace_id = field::Empty);
+        telemetry::record_trace_id(&span);
+
+        async move {
+            let _timer = self.metrics.reconcile(controller);
+            let result = reconciliation.await;
+
+            self.requeue(controller, &key, result)
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Decides what follows a reconciliation of the object identified by `key`.
+    ///
+    /// `error_policy` isn't told which object failed, so failures are handled here instead: retryable
+    /// errors are requeued with a per-object exponential backoff, while permanent errors wait for
+    /// the object to change. A success resets the object's backoff.
+    pub fn requeue(
+        &self,
+        controller: &str,
+        key: &str,
+        result: Result<Action, Error>,
+    ) -> Result<Action, Error> {
+        let error = match result {
+            Ok(action) => {
+                self.backoff.reset(key);
+                return Ok(action);
+            }
+            Err(error) => error,
+        };
+
+        self.metrics.reconcile_failure(controller, &error);
+
+        if !error.is_retryable() {
+            self.backoff.reset(key);
+            warn!(
+                "reconcile of {} failed permanently: {:?}. Waiting for a change",
+                key, error
+            );
+            return Ok(Action::await_change());
+        }
+
+        let delay = self.backoff.next(key);
+        warn!(
+            "reconcile of {} failed: {:?}. Will try again in {:.1} seconds",
+            key,
+            error,
+            delay.as_secs_f64()
+        );
+
+        Ok(Action::requeue(delay))
+    }
+}
+
+impl Data {
+    /// Forgets the backoff of an object the controller found deleted when retrying it, which
+    /// `requeue` never hears of again
+    pub fn forget_deleted<T, QueueErr>(&self, result: &Result<T, controller::Error<Error, QueueErr>>)
+    where
+        QueueErr: std::error::Error + 'static,
+    {
+        if let Err(controller::Error::ObjectNotFound(object)) = result {
+            self.backoff
+                .reset(&object_key(object.namespace.clone(), &object.name));
+        }
+    }
+}
+
+/// Exponential backoff with jitter, tracked per object
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    failures: Mutex<HashMap<String, u32>>,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Backoff {
+            base,
+            max,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records a failure and returns how long to wait before retrying. The delay doubles with each
+    /// consecutive failure up to `max`, and is then jittered into its upper half so that objects
+    /// failing together don't retry together.
+    pub fn next(&self, key: &str) -> Duration {
+        let failures = {
+            let mut failures = self.failures.lock().unwrap();
+            let count = failures.entry(key.to_string()).or_insert(0);
+            *count = count.saturating_add(1);
+            *count
+        };
+
+        let delay = self
+            .base
+            .checked_mul(2u32.saturating_pow(failures - 1))
+            .map_or(self.max, |delay| delay.min(self.max));
+
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    pub fn reset(&self, key: &str) {
+        self.failures.lock().unwrap().remove(key);
+    }
+}
+
+/// Identifies an object across namespaces in logs and the backoff
+pub fn object_key(namespace: Option<String>, name: &str) -> String {
+    match namespace {
+        Some(namespace) => format!("{}/{}", namespace, name),
+        None => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff() -> Backoff {
+        Backoff::new(Duration::from_secs(5), Duration::from_secs(60))
+    }
+
+    fn assert_jittered(delay: Duration, expected: Duration) {
+        assert!(
+            delay >= expected / 2 && delay <= expected,
+            "{:?} not within {:?}",
+            delay,
+            expected
+        );
+    }
+
+    #[test]
+    fn doubles_with_each_failure() {
+        let backoff = backoff();
+
+        for expected in [5, 10, 20, 40] {
+            assert_jittered(backoff.next("default/github"), Duration::from_secs(expected));
+        }
+    }
+
+    #[test]
+    fn caps_the_delay() {
+        let backoff = backoff();
+
+        for _ in 0..40 {
+            assert!(backoff.next("default/github") <= Duration::from_secs(60));
+        }
+        assert_jittered(backoff.next("default/github"), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn resets_after_a_success() {
+        let backoff = backoff();
+        backoff.next("default/github");
+        backoff.next("default/github");
+
+        backoff.reset("default/github");
+
+        assert_jittered(backoff.next("default/github"), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn backs_off_each_object_separately() {
+        let backoff = backoff();
+        backoff.next("default/github");
+        backoff.next("default/github");
+
+        assert_jittered(backoff.next("default/gitlab"), Duration::from_secs(5));
+    }
 }
@@ -0,0 +1,295 @@
+use crate::shutdown::Shutdown;
+use chrono::{Duration as ChronoDuration, Utc};
+use futures::future::BoxFuture;
+use k8s_openapi::{
+    api::coordination::v1::{Lease, LeaseSpec},
+    apimachinery::pkg::apis::meta::v1::MicroTime,
+};
+use kube::{
+    api::{Api, PostParams},
+    core::ObjectMeta,
+    Client,
+};
+use std::sync::Arc;
+use tokio::{
+    sync::watch,
+    time::{self, Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// How long a lease is valid for without being renewed
+const LEASE_DURATION: Duration = Duration::from_secs(15);
+
+/// How long the leader keeps leading without renewing the lease, which is shorter than the lease
+/// so that it stops before another replica can take over
+const RENEW_DEADLINE: Duration = Duration::from_secs(10);
+
+/// How often the leader renews, and candidates retry, the lease
+const RETRY_PERIOD: Duration = Duration::from_secs(5);
+
+/// Observes whether this replica currently holds the leader Lease
+#[derive(Clone)]
+pub struct Leadership {
+    receiver: watch::Receiver<bool>,
+
+    /// Held by leadership which never changes, so the channel is never closed
+    _sender: Option<Arc<watch::Sender<bool>>>,
+}
+
+impl Leadership {
+    /// Leadership for a single replica deployment, which always leads
+    pub fn always() -> Self {
+        let (sender, receiver) = watch::channel(true);
+
+        Leadership {
+            receiver,
+            _sender: Some(Arc::new(sender)),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Resolves once this replica is the leader
+    pub async fn acquired(&mut self) {
+        while !*self.receiver.borrow_and_update() {
+            if self.receiver.changed().await.is_err() {
+                return futures::future::pending().await;
+            }
+        }
+    }
+
+    /// Resolves once this replica is no longer the leader
+    pub async fn lost(&mut self) {
+        while *self.receiver.borrow_and_update() {
+            if self.receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Runs the future built by `controller` whenever this replica leads, dropping it when leadership is lost.
+///
+/// Resolves when the controller finishes, which it should do itself upon `shutdown`, or upon `shutdown`
+/// while this replica is not leading.
+pub async fn while_leading<F>(mut leadership: Leadership, name: &str, shutdown: Shutdown, controller: F)
+where
+    F: Fn() -> BoxFuture<'static, ()>,
+{
+    loop {
+        tokio::select! {
+            _ = leadership.acquired() => {},
+            _ = shutdown.clone() => return,
+        }
+
+        info!("Starting {} controller", name);
+
+        tokio::select! {
+            _ = controller() => return,
+            _ = leadership.lost() => warn!("Stopping {} controller after losing leadership", name),
+        }
+    }
+}
+
+/// Lease based leader election, so that only one replica runs the controllers
+#[derive(Clone)]
+pub struct LeaderElection {
+    leases: Api<Lease>,
+    lease_name: String,
+    identity: String,
+}
+
+impl LeaderElection {
+    pub fn new(client: Client, lease_name: &str, identity: &str) -> Self {
+        LeaderElection {
+            leases: Api::default_namespaced(client),
+            lease_name: lease_name.to_string(),
+            identity: identity.to_string(),
+        }
+    }
+
+    /// Starts competing for the lease, returning a handle to observe leadership and the future
+    /// which must be polled to keep acquiring and renewing it.
+    pub fn run(self) -> (Leadership, impl std::future::Future<Output = ()>) {
+        let (sender, receiver) = watch::channel(false);
+
+        let future = async move {
+            // When the lease was last renewed, by the time the renewal was sent
+            let mut renewed_at: Option<Instant> = None;
+
+            loop {
+                let started_at = Instant::now();
+                let deadline = renewed_at.unwrap_or(started_at) + RENEW_DEADLINE;
+
+                // Leadership ends at the deadline, however long the API server takes to answer
+                let leading = match time::timeout_at(deadline, self.try_acquire_or_renew()).await {
+                    Ok(Ok(true)) => {
+                        renewed_at = Some(started_at);
+                        true
+                    }
+                    Ok(Ok(false)) => false,
+                    Ok(Err(error)) => {
+                        warn!(
+                            "Failed to acquire or renew lease {}: {:?}",
+                            self.lease_name, error
+                        );
+                        renewed_at.is_some_and(|renewed_at| renewed_at.elapsed() < RENEW_DEADLINE)
+                    }
+                    Err(_) => {
+                        warn!("Timed out acquiring or renewing lease {}", self.lease_name);
+                        false
+                    }
+                };
+
+                if !leading {
+                    renewed_at = None;
+                }
+
+                if leading != *sender.borrow() {
+                    match leading {
+                        true => info!("{} is now the leader", self.identity),
+                        false => warn!("{} is no longer the leader", self.identity),
+                    }
+
+                    let _ = sender.send(leading);
+                }
+
+                // A leader which failed to renew retries no later than its deadline, so that it gives up
+                // leadership on time
+                match renewed_at {
+                    Some(renewed_at) => {
+                        time::sleep_until((Instant::now() + RETRY_PERIOD).min(renewed_at + RENEW_DEADLINE))
+                            .await
+                    }
+                    None => time::sleep(RETRY_PERIOD).await,
+                }
+            }
+        };
+
+        (
+            Leadership {
+                receiver,
+                _sender: None,
+            },
+            future,
+        )
+    }
+
+    /// Gives up the lease if this replica holds it, so that another replica can lead without waiting for
+    /// it to expire. Call once `run`'s future has been dropped and the controllers have stopped.
+    pub async fn release(&self) -> Result<(), kube::Error> {
+        let lease = match self.leases.get_opt(&self.lease_name).await? {
+            Some(lease) => lease,
+            None => return Ok(()),
+        };
+
+        let spec = lease.spec.clone().unwrap_or_default();
+        if spec.holder_identity.as_deref() != Some(self.identity.as_str()) {
+            return Ok(());
+        }
+
+        // As with renewing, the observed resourceVersion keeps a lease taken over since from being released
+        let released = Lease {
+            metadata: lease.metadata,
+            spec: Some(LeaseSpec {
+                holder_identity: None,
+                lease_duration_seconds: Some(1),
+                acquire_time: None,
+                renew_time: Some(MicroTime(Utc::now())),
+                ..spec
+            }),
+        };
+
+        match self
+            .leases
+            .replace(&self.lease_name, &PostParams::default(), &released)
+            .await
+        {
+            Ok(_) => {
+                info!("{} released the lease {}", self.identity, self.lease_name);
+                Ok(())
+            }
+            Err(kube::Error::Api(response)) if response.code == 409 => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+
+    async fn try_acquire_or_renew(&self) -> Result<bool, kube::Error> {
+        let now = Utc::now();
+
+        let lease = match self.leases.get_opt(&self.lease_name).await? {
+            Some(lease) => lease,
+            None => {
+                let lease = Lease {
+                    metadata: ObjectMeta {
+                        name: Some(self.lease_name.clone()),
+                        ..ObjectMeta::default()
+                    },
+                    spec: Some(self.spec(now, None, 0)),
+                };
+
+                return match self.leases.create(&PostParams::default(), &lease).await {
+                    Ok(_) => Ok(true),
+                    Err(kube::Error::Api(response)) if response.code == 409 => Ok(false),
+                    Err(error) => Err(error),
+                };
+            }
+        };
+
+        let spec = lease.spec.clone().unwrap_or_default();
+        let holder = spec.holder_identity.clone().unwrap_or_default();
+
+        let expired = match &spec.renew_time {
+            Some(MicroTime(renew_time)) => {
+                let duration = spec
+                    .lease_duration_seconds
+                    .unwrap_or(LEASE_DURATION.as_secs() as i32);
+                *renew_time + ChronoDuration::seconds(duration as i64) < now
+            }
+            None => true,
+        };
+
+        if holder != self.identity && !expired {
+            return Ok(false);
+        }
+
+        let transitions = spec.lease_transitions.unwrap_or(0);
+        let new_spec = match holder == self.identity {
+            true => self.spec(now, spec.acquire_time, transitions),
+            false => self.spec(now, None, transitions + 1),
+        };
+
+        // Replacing with the observed resourceVersion ensures only one candidate wins a race
+        let renewed = Lease {
+            metadata: lease.metadata,
+            spec: Some(new_spec),
+        };
+
+        match self
+            .leases
+            .replace(&self.lease_name, &PostParams::default(), &renewed)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(kube::Error::Api(response)) if response.code == 409 => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn spec(
+        &self,
+        now: chrono::DateTime<Utc>,
+        acquire_time: Option<MicroTime>,
+        transitions: i32,
+    ) -> LeaseSpec {
+        LeaseSpec {
+            holder_identity: Some(self.identity.clone()),
+            lease_duration_seconds: Some(LEASE_DURATION.as_secs() as i32),
+            acquire_time: acquire_time.or(Some(MicroTime(now))),
+            renew_time: Some(MicroTime(now)),
+            lease_transitions: Some(transitions),
+        }
+    }
+}
@@ -1,4 +1,7 @@
 pub mod controller;
+pub mod leader;
+pub mod reflector;
+pub(crate) mod schema;
 
 mod secrets;
 pub use secrets::get_string_value;
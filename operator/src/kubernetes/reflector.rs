@@ -0,0 +1,60 @@
+use futures::{future::BoxFuture, FutureExt, StreamExt};
+use kube::{
+    api::{Api, ListParams},
+    runtime::{
+        reflector::{self, store::Writer, Store},
+        utils::StreamBackoff,
+        watcher::{self, watcher},
+    },
+    Resource,
+};
+use serde::de::DeserializeOwned;
+use std::{
+    fmt::Debug,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tracing::warn;
+
+/// Whether a reflector has completed its initial list, so its `Store` reflects the cluster
+#[derive(Clone, Default)]
+pub struct Synced(Arc<AtomicBool>);
+
+impl Synced {
+    pub fn get(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Watches `api` into a `Store`, independently of any controller, tracking when it has synced.
+///
+/// The returned future drives the watch and must be polled for the `Store` to be populated.
+pub fn reflect<K>(api: Api<K>) -> (Store<K>, Synced, BoxFuture<'static, ()>)
+where
+    K: Resource + Clone + DeserializeOwned + Debug + Send + Sync + 'static,
+    K::DynamicType: Default + Eq + Hash + Clone,
+{
+    let writer = Writer::<K>::default();
+    let store = writer.as_reader();
+    let synced = Synced::default();
+
+    let stream = StreamBackoff::new(watcher(api, ListParams::default()), watcher::default_backoff());
+
+    let flag = synced.clone();
+    let future = reflector::reflector(writer, stream)
+        .for_each(move |event| {
+            match event {
+                Ok(watcher::Event::Restarted(_)) => flag.0.store(true, Ordering::Relaxed),
+                Ok(_) => {}
+                Err(error) => warn!("Reflector watch failed: {:?}", error),
+            };
+
+            futures::future::ready(())
+        })
+        .boxed();
+
+    (store, synced, future)
+}
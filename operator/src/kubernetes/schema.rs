@@ -0,0 +1,26 @@
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde_json::json;
+
+/// Schema of `T` with CEL validation rules, as `(rule, message)` pairs. Rules are enforced by API
+/// servers from 1.25, and ignored by older ones.
+pub(crate) fn with_rules<T: JsonSchema>(gen: &mut SchemaGenerator, rules: &[(&str, &str)]) -> Schema {
+    let mut schema = gen.subschema_for::<T>().into_object();
+
+    schema.extensions.insert(
+        String::from("x-kubernetes-validations"),
+        rules
+            .iter()
+            .map(|(rule, message)| json!({ "rule": rule, "message": message }))
+            .collect(),
+    );
+
+    Schema::Object(schema)
+}
+
+/// A non-empty string which can't be changed once set
+pub(crate) fn immutable_string(gen: &mut SchemaGenerator) -> Schema {
+    let mut schema = with_rules::<String>(gen, &[("self == oldSelf", "is immutable")]).into_object();
+    schema.string().min_length = Some(1);
+
+    Schema::Object(schema)
+}
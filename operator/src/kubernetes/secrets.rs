@@ -3,10 +3,10 @@ use k8s_openapi::api::core::v1::Secret;
 #[derive(Debug)]
 enum Decoded {
     Utf8(String),
-    Bytes(Vec<u8>),
+    Bytes(#[allow(dead_code)] Vec<u8>),
 }
 
-fn decode(secret: &Secret, key: &String) -> Result<Decoded, crate::Error> {
+fn decode(secret: &Secret, key: &str) -> Result<Decoded, crate::Error> {
     let encoded_value = match &secret.data {
         Some(map) => match map.get(key) {
             Some(value) => value,
@@ -32,7 +32,7 @@ fn decode(secret: &Secret, key: &String) -> Result<Decoded, crate::Error> {
     }
 }
 
-pub fn get_string_value(secret: &Secret, key: &String) -> Result<String, crate::Error> {
+pub fn get_string_value(secret: &Secret, key: &str) -> Result<String, crate::Error> {
     match decode(secret, key) {
         Ok(Decoded::Utf8(value)) => Ok(value),
         Err(error) => Err(error),
@@ -1,19 +1,37 @@
-use axum::response::IntoResponse;
+use axum::{response::IntoResponse, Json};
 use hyper::StatusCode;
-use kube::runtime::reflector::Store;
+use kube::runtime::{events::Reporter, reflector::Store};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use thiserror::Error;
-use tracing::subscriber::SetGlobalDefaultError;
+use tracing::{subscriber::SetGlobalDefaultError, warn};
 use tracing_subscriber::filter::ParseError;
 
+pub mod authentication;
+pub mod config;
+use crate::config::Config;
+pub mod health;
 pub mod kubernetes;
+pub mod metrics;
+use crate::{kubernetes::leader::Leadership, metrics::Metrics};
 pub mod oauth_api;
 use crate::oauth_api::OAuthApi;
 pub mod oauth_connection;
-use crate::oauth_connection::OAuthConnection;
+use crate::oauth_connection::{OAuthConnection, PendingAuthorizations, TokenRefreshes};
+pub mod schema;
+pub mod shutdown;
+use crate::shutdown::Shutdown;
+pub mod telemetry;
+pub mod ui;
+pub mod webhook;
 
 const RESOURCE_NAMESPACE: &str = "chappaai.dev";
 const RESOURCE_VERSION: &str = "v1";
 
+/// Version of the JSON documents served by the HTTP API. Bumped on breaking changes.
+pub const WEB_API_VERSION: &str = "v1";
+
 fn api_version() -> String {
     format!("{}/{}", RESOURCE_NAMESPACE, RESOURCE_VERSION)
 }
@@ -28,6 +46,18 @@ pub struct ApplicationState {
     pub client: kube::Client,
     pub oauth_apis: Store<OAuthApi>,
     pub oauth_connections: Store<OAuthConnection>,
+    pub authorizations: PendingAuthorizations,
+    pub refreshes: TokenRefreshes,
+    /// Shared by requests to APIs, so that their connections are pooled
+    pub http: reqwest::Client,
+    pub reporter: Reporter,
+    pub metrics: Arc<Metrics>,
+    pub oauth_api_manager: oauth_api::Manager,
+    pub oauth_connection_manager: oauth_connection::Manager,
+    pub leadership: Leadership,
+    /// Resolved once shutdown begins, after which this replica reports itself unready
+    pub shutdown: Shutdown,
+    pub config: Arc<Config>,
 }
 
 #[derive(Error, Debug)]
@@ -49,12 +79,179 @@ pub enum Error {
 
     #[error("ParseError: {0}")]
     ParseError(#[from] ParseError),
+
+    #[error("OAuthConnection {0} not found")]
+    ConnectionNotFound(String),
+
+    #[error("OAuthApi {0} not found")]
+    ApiNotFound(String),
+
+    #[error("Client credentials unavailable: {0}")]
+    CredentialsMissing(String),
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("Authorization state does not match a pending authorization")]
+    StateMismatch,
+
+    #[error("Authorization denied by provider: {0}")]
+    AuthorizationDenied(String),
+
+    #[error("Token exchange failed: {0}")]
+    TokenExchangeFailed(String),
+
+    #[error("Failed to store token: {0}")]
+    TokenStorageFailed(String),
+
+    #[error("Invalid configuration: {0}")]
+    ConfigError(String),
+
+    #[error("Invalid spec: {0}")]
+    InvalidSpec(String),
+
+    #[error("Certificate Error: {0}")]
+    CertificateError(String),
+
+    #[error("OAuthConnection {0} is not connected")]
+    NotConnected(String),
+
+    #[error("Token refresh failed: {0}")]
+    TokenRefreshFailed(String),
+
+    #[error("Upstream request failed: {0}")]
+    UpstreamError(String),
+
+    #[error("Unauthenticated: {0}")]
+    Unauthenticated(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Request body exceeds {0} bytes")]
+    PayloadTooLarge(usize),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+impl Error {
+    /// Machine readable code, stable across releases, for HTTP API consumers
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::ConnectionNotFound(_) => "connection_not_found",
+            Error::ApiNotFound(_) => "api_not_found",
+            Error::CredentialsMissing(_) => "credentials_missing",
+            Error::InvalidRequest(_) => "invalid_request",
+            Error::StateMismatch => "state_mismatch",
+            Error::AuthorizationDenied(_) => "authorization_denied",
+            Error::TokenExchangeFailed(_) => "token_exchange_failed",
+            Error::TokenStorageFailed(_) => "token_storage_failed",
+            Error::NotConnected(_) => "not_connected",
+            Error::TokenRefreshFailed(_) => "token_refresh_failed",
+            Error::UpstreamError(_) => "upstream_error",
+            Error::Unauthenticated(_) => "unauthenticated",
+            Error::Forbidden(_) => "forbidden",
+            Error::PayloadTooLarge(_) => "payload_too_large",
+            Error::InvalidSpec(_) => "invalid_spec",
+            Error::KubeError(_) => "kubernetes_error",
+            _ => "internal_error",
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Error::ConnectionNotFound(_) | Error::ApiNotFound(_) => StatusCode::NOT_FOUND,
+            Error::CredentialsMissing(_) | Error::NotConnected(_) | Error::InvalidSpec(_) => {
+                StatusCode::CONFLICT
+            }
+            Error::InvalidRequest(_) | Error::StateMismatch => StatusCode::BAD_REQUEST,
+            Error::Unauthenticated(_) => StatusCode::UNAUTHORIZED,
+            Error::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Error::AuthorizationDenied(_) | Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::TokenExchangeFailed(_) | Error::TokenRefreshFailed(_) | Error::UpstreamError(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Whether reconciling again, without the resource changing, could succeed. Permanent
+    /// errors, such as an invalid spec or a request the API server rejects, wait for a change.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::InvalidSpec(_) | Error::SerializationError(_) => false,
+            Error::KubeError(kube::Error::Api(response)) => {
+                !matches!(response.code, 400 | 405 | 413 | 415 | 422)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// JSON body returned by the HTTP API for every failed request
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ErrorResponse {
+    pub error: ErrorDetail,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct ErrorDetail {
+    pub code: String,
+    pub message: String,
+}
+
+impl From<&Error> for ErrorResponse {
+    fn from(error: &Error) -> Self {
+        ErrorResponse {
+            error: ErrorDetail {
+                code: error.code().to_string(),
+                message: error.to_string(),
+            },
+        }
+    }
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        let status = self.status_code();
+
+        if status.is_server_error() {
+            warn!("Request failed: {:?}", self);
+        }
+
+        (status, Json(ErrorResponse::from(&self))).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::error::ErrorResponse as ApiErrorResponse;
+
+    fn api_error(code: u16) -> Error {
+        Error::KubeError(kube::Error::Api(ApiErrorResponse {
+            status: String::from("Failure"),
+            message: String::new(),
+            reason: String::new(),
+            code,
+        }))
+    }
+
+    #[test]
+    fn retries_transient_errors() {
+        assert!(Error::TokenRefreshFailed(String::from("timed out")).is_retryable());
+        assert!(api_error(409).is_retryable());
+        assert!(api_error(429).is_retryable());
+        assert!(api_error(500).is_retryable());
+    }
+
+    #[test]
+    fn does_not_retry_permanent_errors() {
+        assert!(!Error::InvalidSpec(String::from("auth is required")).is_retryable());
+        assert!(!Error::SerializationError(serde_json::from_str::<u8>("").unwrap_err()).is_retryable());
+
+        for code in [400, 405, 413, 415, 422] {
+            assert!(!api_error(code).is_retryable(), "{}", code);
+        }
     }
 }
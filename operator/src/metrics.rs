@@ -0,0 +1,252 @@
+use crate::{oauth_connection::OAuthConnectionPhase, ApplicationState, Error};
+
+use axum::{
+    extract::MatchedPath,
+    http::{header::CONTENT_TYPE, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use chrono::{DateTime, Utc};
+use kube::ResourceExt;
+use prometheus::{
+    histogram_opts, opts, Encoder, GaugeVec, HistogramTimer, HistogramVec, IntCounterVec, IntGaugeVec,
+    Registry, TextEncoder,
+};
+use std::{sync::Arc, time::Instant};
+
+/// Prometheus collectors shared by the controllers and the HTTP API
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pub reconciliations: IntCounterVec,
+    pub reconcile_failures: IntCounterVec,
+    pub reconcile_duration: HistogramVec,
+    pub connections: IntGaugeVec,
+    pub token_expiry: GaugeVec,
+    pub token_exchanges: IntCounterVec,
+    pub token_refreshes: IntCounterVec,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let reconciliations = IntCounterVec::new(
+            opts!(
+                "chappaai_reconciliations_total",
+                "Reconciliations, per controller"
+            ),
+            &["controller"],
+        )
+        .unwrap();
+        let reconcile_failures = IntCounterVec::new(
+            opts!(
+                "chappaai_reconcile_failures_total",
+                "Failed reconciliations, per controller and error"
+            ),
+            &["controller", "error"],
+        )
+        .unwrap();
+        let reconcile_duration = HistogramVec::new(
+            histogram_opts!(
+                "chappaai_reconcile_duration_seconds",
+                "Duration of reconciliations, per controller",
+                vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0]
+            ),
+            &["controller"],
+        )
+        .unwrap();
+        let connections = IntGaugeVec::new(opts!("chappaai_connections", "OAuthConnections, per phase"), &[
+            "phase",
+        ])
+        .unwrap();
+        let token_expiry = GaugeVec::new(
+            opts!(
+                "chappaai_token_expiry_seconds",
+                "Seconds until the token of a connection expires"
+            ),
+            &["namespace", "connection", "api"],
+        )
+        .unwrap();
+        let token_exchanges = IntCounterVec::new(
+            opts!(
+                "chappaai_token_exchanges_total",
+                "Authorization code exchanges, per API and result"
+            ),
+            &["api", "result"],
+        )
+        .unwrap();
+        let token_refreshes = IntCounterVec::new(
+            opts!(
+                "chappaai_token_refreshes_total",
+                "Token refreshes, per API and result"
+            ),
+            &["api", "result"],
+        )
+        .unwrap();
+        let http_requests = IntCounterVec::new(
+            opts!(
+                "chappaai_http_requests_total",
+                "HTTP requests, per route and status"
+            ),
+            &["method", "path", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            histogram_opts!(
+                "chappaai_http_request_duration_seconds",
+                "Duration of HTTP requests, per route",
+                vec![0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0]
+            ),
+            &["method", "path"],
+        )
+        .unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(reconciliations.clone())).unwrap();
+        registry.register(Box::new(reconcile_failures.clone())).unwrap();
+        registry.register(Box::new(reconcile_duration.clone())).unwrap();
+        registry.register(Box::new(connections.clone())).unwrap();
+        registry.register(Box::new(token_expiry.clone())).unwrap();
+        registry.register(Box::new(token_exchanges.clone())).unwrap();
+        registry.register(Box::new(token_refreshes.clone())).unwrap();
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            reconciliations,
+            reconcile_failures,
+            reconcile_duration,
+            connections,
+            token_expiry,
+            token_exchanges,
+            token_refreshes,
+            http_requests,
+            http_request_duration,
+        }
+    }
+}
+
+impl Metrics {
+    /// Counts a reconciliation, returning a timer which observes its duration when dropped
+    pub fn reconcile(&self, controller: &str) -> HistogramTimer {
+        self.reconciliations.with_label_values(&[controller]).inc();
+        self.reconcile_duration
+            .with_label_values(&[controller])
+            .start_timer()
+    }
+
+    pub fn reconcile_failure(&self, controller: &str, error: &Error) {
+        self.reconcile_failures
+            .with_label_values(&[controller, error.code()])
+            .inc();
+    }
+
+    pub fn token_exchange(&self, api: &str, success: bool) {
+        self.token_exchanges
+            .with_label_values(&[api, result(success)])
+            .inc();
+    }
+
+    pub fn token_refresh(&self, api: &str, success: bool) {
+        self.token_refreshes
+            .with_label_values(&[api, result(success)])
+            .inc();
+    }
+
+    /// Connection gauges are derived from the reflector store at scrape time, so they never go stale
+    fn observe_connections(&self, state: &ApplicationState) {
+        self.connections.reset();
+        self.token_expiry.reset();
+
+        for phase in [
+            OAuthConnectionPhase::Initializing,
+            OAuthConnectionPhase::Disconnected,
+            OAuthConnectionPhase::Connected,
+        ] {
+            self.connections
+                .with_label_values(&[&String::from(&phase)])
+                .set(0);
+        }
+
+        for oauth_connection in state.oauth_connections.state() {
+            let status = oauth_connection.status.clone().unwrap_or_default();
+
+            let phase = match &status.phase {
+                Some(phase) => String::from(phase),
+                None => String::from("Unknown"),
+            };
+            self.connections.with_label_values(&[&phase]).inc();
+
+            let expires_at = status
+                .expires_at
+                .as_deref()
+                .and_then(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok());
+
+            if let Some(expires_at) = expires_at {
+                let remaining = expires_at.with_timezone(&Utc) - Utc::now();
+
+                self.token_expiry
+                    .with_label_values(&[
+                        &oauth_connection.namespace().unwrap_or_default(),
+                        &oauth_connection.name(),
+                        &oauth_connection.spec.api,
+                    ])
+                    .set(remaining.num_milliseconds() as f64 / 1000.0);
+            }
+        }
+    }
+}
+
+fn result(success: bool) -> &'static str {
+    match success {
+        true => "success",
+        false => "failure",
+    }
+}
+
+pub async fn handler(Extension(state): Extension<Arc<ApplicationState>>) -> Response {
+    state.metrics.observe_connections(&state);
+
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+
+    if let Err(e) = encoder.encode(&state.metrics.registry.gather(), &mut buffer) {
+        return Error::GenericError(format!("Failed to encode metrics: {}", e)).into_response();
+    }
+
+    ([(CONTENT_TYPE, encoder.format_type().to_string())], buffer).into_response()
+}
+
+/// Middleware recording the count and duration of requests, labelled by route rather than raw path
+pub async fn track_http<B>(request: Request<B>, next: Next<B>) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let path = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None => String::from("unmatched"),
+    };
+    let metrics = request
+        .extensions()
+        .get::<Arc<ApplicationState>>()
+        .map(|state| state.metrics.clone());
+
+    let response = next.run(request).await;
+
+    if let Some(metrics) = metrics {
+        metrics
+            .http_requests
+            .with_label_values(&[&method, &path, response.status().as_str()])
+            .inc();
+        metrics
+            .http_request_duration
+            .with_label_values(&[&method, &path])
+            .observe(start.elapsed().as_secs_f64());
+    }
+
+    response
+}
@@ -1,9 +1,56 @@
 use std::sync::Arc;
 
-use crate::{ApplicationState, Result};
+use super::{AuthSpecs, OAuthApi, OAuthApiStatus};
+use crate::{ApplicationState, Error, Result, WEB_API_VERSION};
 
-use axum::{Extension, Json};
+use axum::{extract::Path, Extension, Json};
+use kube::ResourceExt;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
 
+/// JSON representation of an `OAuthApi`, versioned by `WEB_API_VERSION`
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OAuthApiWeb {
+    pub api_version: String,
+    pub kind: String,
+    pub name: String,
+    pub namespace: Option<String>,
+    pub phase: Option<String>,
+    pub base_url: String,
+    pub authorization_url: Option<String>,
+    pub token_url: Option<String>,
+    pub connections: Vec<String>,
+}
+
+impl OAuthApiWeb {
+    fn new(oauth_api: &OAuthApi, connections: Vec<String>) -> Self {
+        let (authorization_url, token_url) = match &oauth_api.spec.auth {
+            Some(AuthSpecs::OAuth2(spec)) => {
+                (Some(spec.authorization_url.clone()), Some(spec.token_url.clone()))
+            }
+            None => (None, None),
+        };
+
+        OAuthApiWeb {
+            api_version: String::from(WEB_API_VERSION),
+            kind: String::from("OAuthApi"),
+            name: oauth_api.name(),
+            namespace: oauth_api.namespace(),
+            phase: match &oauth_api.status {
+                Some(OAuthApiStatus { phase: Some(phase) }) => Some(format!("{:?}", phase)),
+                _ => None,
+            },
+            base_url: oauth_api.spec.http.base_url.clone(),
+            authorization_url,
+            token_url,
+            connections,
+        }
+    }
+}
+
+#[instrument(skip_all)]
 pub async fn list(Extension(state): Extension<Arc<ApplicationState>>) -> Result<Json<Vec<String>>> {
     let oauth_apis = &state.oauth_apis.state();
 
@@ -17,3 +64,26 @@ pub async fn list(Extension(state): Extension<Arc<ApplicationState>>) -> Result<
 
     Ok(Json(oauth_api_names))
 }
+
+#[instrument(skip_all, fields(api = %name))]
+pub async fn get(
+    Path(name): Path<String>,
+    Extension(state): Extension<Arc<ApplicationState>>,
+) -> Result<Json<OAuthApiWeb>> {
+    let oauth_api = state
+        .oauth_apis
+        .state()
+        .into_iter()
+        .find(|api| api.name() == name)
+        .ok_or_else(|| Error::ApiNotFound(name.clone()))?;
+
+    let connections = state
+        .oauth_connections
+        .state()
+        .iter()
+        .filter(|connection| connection.spec.api == name)
+        .map(|connection| connection.name())
+        .collect();
+
+    Ok(Json(OAuthApiWeb::new(&oauth_api, connections)))
+}
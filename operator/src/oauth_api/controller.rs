@@ -1,5 +1,16 @@
 use super::{OAuthApi, OAuthApiPhase, OAuthApiStatus};
-use crate::{api_version, kubernetes::controller, Error};
+use crate::{
+    api_version,
+    config::Config,
+    kubernetes::{
+        controller,
+        leader::{self, Leadership},
+        reflector::{self, Synced},
+    },
+    metrics::Metrics,
+    shutdown::Shutdown,
+    Error,
+};
 use chrono::prelude::*;
 use futures::{future::BoxFuture, FutureExt, StreamExt};
 use kube::{
@@ -14,8 +25,10 @@ use kube::{
 };
 use serde_json::json;
 use std::sync::Arc;
-use tokio::{sync::RwLock, time::Duration};
-use tracing::{info, warn};
+use tokio::sync::RwLock;
+use tracing::{info, instrument, warn};
+
+const CONTROLLER_NAME: &str = "oauth-apis";
 
 #[derive(Clone)]
 pub struct Manager {
@@ -24,6 +37,9 @@ pub struct Manager {
 
     /// In memory state
     state: Arc<RwLock<controller::State>>,
+
+    /// Whether the store served to the HTTP API has synced
+    synced: Synced,
 }
 
 /// Example Manager that owns a Controller for Foo
@@ -32,12 +48,20 @@ impl Manager {
     ///
     /// This returns a `Manager` that drives a `Controller` + a future to be awaited
     /// It is up to `main` to wait for the controller stream.
-    pub async fn new(client: Client) -> (Self, Store<OAuthApi>, BoxFuture<'static, ()>) {
-        let state = Arc::new(RwLock::new(controller::State::new(String::from("oauth-apis"))));
-        let context = Context::new(controller::Data {
-            client: client.clone(),
-            state: state.clone(),
-        });
+    pub async fn new(
+        client: Client,
+        config: Arc<Config>,
+        metrics: Arc<Metrics>,
+        leadership: Leadership,
+        shutdown: Shutdown,
+    ) -> (Self, Store<OAuthApi>, BoxFuture<'static, ()>) {
+        let state = Arc::new(RwLock::new(controller::State::new(String::from(CONTROLLER_NAME))));
+        let context = Context::new(controller::Data::new(
+            client.clone(),
+            state.clone(),
+            metrics,
+            config,
+        ));
 
         let api_services = Api::<OAuthApi>::default_namespaced(client.clone());
 
@@ -47,31 +71,68 @@ impl Manager {
             Err(err) => panic!("Failed to list: {:?}", err),
         }
 
-        // All good. Start controller and return its future.
-        let drainer = Controller::new(api_services, ListParams::default());
-        let store = drainer.store();
-
-        let drainer = drainer
-            .run(reconcile, error_policy, context)
-            .filter_map(|x| async move { std::result::Result::ok(x) })
-            .for_each(|_| futures::future::ready(()))
+        // The HTTP API reads from its own reflector on every replica, while the controller
+        // only runs on the leader.
+        let (store, synced, reflector) = reflector::reflect(api_services.clone());
+
+        let drainer = leader::while_leading(leadership, CONTROLLER_NAME, shutdown.clone(), move || {
+            Controller::new(api_services.clone(), ListParams::default())
+                .graceful_shutdown_on(shutdown.clone())
+                .run(reconcile, error_policy, context.clone())
+                .for_each({
+                    let context = context.clone();
+                    move |result| {
+                        context.get_ref().forget_deleted(&result);
+                        futures::future::ready(())
+                    }
+                })
+                .boxed()
+        });
+        let drainer = futures::future::select(drainer.boxed(), reflector)
+            .map(|_| ())
             .boxed();
 
-        (Self { client, state }, store, drainer)
+        (
+            Self {
+                client,
+                state,
+                synced,
+            },
+            store,
+            drainer,
+        )
     }
 
     /// State getter
     pub async fn state(&self) -> controller::State {
         self.state.read().await.clone()
     }
+
+    /// Whether the reflector store has completed its initial list
+    pub fn synced(&self) -> bool {
+        self.synced.get()
+    }
 }
 
-fn error_policy(error: &Error, _: Context<controller::Data>) -> Action {
-    warn!("reconcile failed: {:?}. Will try again in 5 minutes", error);
-    Action::requeue(Duration::from_secs(5 * 60))
+/// Failures are requeued by `reconcile`, which knows the object that failed, so this only
+/// guards against errors escaping it.
+fn error_policy(error: &Error, ctx: Context<controller::Data>) -> Action {
+    warn!("reconcile failed: {:?}", error);
+    Action::requeue(ctx.get_ref().config.error_backoff_max())
 }
 
+#[instrument(skip_all, fields(api = %api_service.name()))]
 async fn reconcile(api_service: Arc<OAuthApi>, ctx: Context<controller::Data>) -> Result<Action, Error> {
+    ctx.get_ref()
+        .reconcile(
+            CONTROLLER_NAME,
+            api_service.as_ref(),
+            register(api_service.clone(), ctx.clone()),
+        )
+        .await
+}
+
+async fn register(api_service: Arc<OAuthApi>, ctx: Context<controller::Data>) -> Result<Action, Error> {
     let client = ctx.get_ref().client.clone();
     ctx.get_ref().state.write().await.last_event = Utc::now();
 
@@ -81,6 +142,21 @@ async fn reconcile(api_service: Arc<OAuthApi>, ctx: Context<controller::Data>) -
     let name = api_service.name();
     let namespace = api_service.namespace();
 
+    if let Err(error) = api_service.validate() {
+        recorder
+            .publish(Event {
+                type_: EventType::Warning,
+                action: "Validating".into(),
+                secondary: None,
+                reason: "InvalidSpec".into(),
+                note: Some(error.to_string()),
+            })
+            .await
+            .map_err(Error::KubeError)?;
+
+        return Err(error);
+    }
+
     let api_services: Api<OAuthApi> = match namespace {
         Some(namespace) => Api::namespaced(client, &namespace),
         None => Api::default_namespaced(client),
@@ -95,7 +171,11 @@ async fn reconcile(api_service: Arc<OAuthApi>, ctx: Context<controller::Data>) -
     }));
 
     let _ = api_services
-        .patch_status(&name, &PatchParams::apply("chappaai").force(), &new_status)
+        .patch_status(
+            &name,
+            &PatchParams::apply(&ctx.get_ref().config.field_manager).force(),
+            &new_status,
+        )
         .await
         .map_err(Error::KubeError)?;
 
@@ -4,4 +4,10 @@ mod controller;
 pub use controller::Manager;
 
 mod resource;
-pub use resource::{OAuthApi, OAuthApiPhase, OAuthApiSpec, OAuthApiStatus};
+pub(crate) use resource::{unreserved_params, validate_unreserved_params};
+pub use resource::{
+    AuthSpecs, AuthorizationParams, IdentitySpec, OAuth2Spec, OAuthApi, OAuthApiPhase, OAuthApiSpec,
+    OAuthApiStatus, TokenContentType, TokenEndpointAuthMethod, TokenResponseMapping,
+};
+
+pub mod v2;
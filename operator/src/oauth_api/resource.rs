@@ -1,6 +1,9 @@
+use crate::{kubernetes::schema, Error, Result};
 use kube::CustomResource;
-use schemars::JsonSchema;
+use oauth2::url::Url;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
 #[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
 #[kube(
@@ -8,6 +11,11 @@ use serde::{Deserialize, Serialize};
     version = "v1",
     kind = "OAuthApi",
     status = "OAuthApiStatus",
+    shortname = "oapi",
+    category = "chappaai",
+    printcolumn = r#"{"name":"Status", "type":"string", "description":"registration status", "jsonPath":".status.phase"}"#,
+    printcolumn = r#"{"name":"Base URL", "type":"string", "description":"base URL of the API", "jsonPath":".spec.http.baseUrl"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#,
     namespaced
 )]
 #[serde(rename_all = "camelCase")]
@@ -17,38 +25,249 @@ pub struct OAuthApiSpec {
 }
 
 impl OAuthApi {
-    pub fn get_authorization_url(&self) -> String {
+    pub fn get_authorization_url(&self) -> Result<String> {
+        self.required_oauth2().map(|spec| spec.authorization_url.clone())
+    }
+
+    pub fn oauth2(&self) -> Option<&OAuth2Spec> {
         match &self.spec.auth {
-            Some(AuthSpecs::OAuth2(spec)) => {
-                format!("{}?{}", spec.authorization_url, spec.get_authorization_params())
-            }
-            _ => todo!(),
+            Some(AuthSpecs::OAuth2(spec)) => Some(spec),
+            None => None,
         }
     }
 
-    pub fn get_token_url(&self) -> String {
+    /// How the client authenticates itself to the token endpoint
+    pub fn token_endpoint_auth_method(&self) -> TokenEndpointAuthMethod {
+        self.oauth2()
+            .map(|spec| spec.token_endpoint_auth_method)
+            .unwrap_or_default()
+    }
+
+    /// How to read the token endpoint's responses
+    pub fn token_response(&self) -> TokenResponseMapping {
+        self.oauth2()
+            .and_then(|spec| spec.token_response.clone())
+            .unwrap_or_default()
+    }
+
+    /// Checks what the CRD schema can't: that an auth spec is present, every URL is absolute and no
+    /// scope is blank
+    pub fn validate(&self) -> Result<()> {
+        validate_url("http.baseUrl", &self.spec.http.base_url)?;
+
+        // Before Kubernetes 1.25 the schema's uniqueness rule isn't enforced
+        validate_unique_keys(
+            "http.headers",
+            self.spec.http.headers.iter().map(|header| header.key.as_str()),
+        )?;
+
         match &self.spec.auth {
-            Some(AuthSpecs::OAuth2(spec)) => format!("{}", spec.token_url),
-            _ => todo!(),
+            Some(AuthSpecs::OAuth2(spec)) => {
+                validate_url("auth.oAuth2.authorizationUrl", &spec.authorization_url)?;
+                validate_url("auth.oAuth2.tokenUrl", &spec.token_url)?;
+
+                if let Some(refresh_url) = &spec.refresh_url {
+                    validate_url("auth.oAuth2.refreshUrl", refresh_url)?;
+                }
+
+                validate_unique_keys(
+                    "auth.oAuth2.authorizationParams",
+                    spec.authorization_params.iter().map(|param| param.key.as_str()),
+                )?;
+                validate_unique_keys(
+                    "auth.oAuth2.extraTokenParams",
+                    spec.extra_token_params.iter().map(|param| param.key.as_str()),
+                )?;
+                validate_unreserved_params("auth.oAuth2.authorizationParams", &spec.authorization_params)?;
+                validate_unreserved_params("auth.oAuth2.extraTokenParams", &spec.extra_token_params)?;
+
+                if spec
+                    .default_scopes
+                    .iter()
+                    .chain(&spec.required_scopes)
+                    .any(|scope| scope.trim().is_empty())
+                {
+                    return Err(Error::InvalidSpec(String::from(
+                        "auth.oAuth2 scopes must not be blank",
+                    )));
+                }
+
+                Ok(())
+            }
+            None => Err(Error::InvalidSpec(String::from("auth is required"))),
         }
     }
+
+    pub fn get_token_url(&self) -> Result<String> {
+        self.required_oauth2().map(|spec| spec.token_url.clone())
+    }
+
+    /// Where refresh tokens are exchanged, which is the token URL unless a `refreshUrl` is given
+    pub fn get_refresh_url(&self) -> Result<String> {
+        self.required_oauth2()
+            .map(|spec| spec.refresh_url.clone().unwrap_or_else(|| spec.token_url.clone()))
+    }
+
+    /// The OAuth2 spec, which an API whose `auth` has been removed since a connection was made lacks
+    fn required_oauth2(&self) -> Result<&OAuth2Spec> {
+        self.oauth2().ok_or_else(|| {
+            Error::InvalidSpec(format!(
+                "OAuthApi {} has no auth",
+                self.metadata.name.as_deref().unwrap_or_default()
+            ))
+        })
+    }
+}
+
+fn validate_url(field: &str, value: &str) -> Result<()> {
+    match Url::parse(value) {
+        Ok(url) if url.scheme() == "https" || url.scheme() == "http" => Ok(()),
+        Ok(url) => Err(Error::InvalidSpec(format!(
+            "{} must be an http(s) URL, not {}",
+            field,
+            url.scheme()
+        ))),
+        Err(error) => Err(Error::InvalidSpec(format!(
+            "{} {:?} is invalid: {}",
+            field, value, error
+        ))),
+    }
+}
+
+/// Keys may only be given once, as `v2` holds them in maps
+fn validate_unique_keys<'a>(field: &str, keys: impl Iterator<Item = &'a str>) -> Result<()> {
+    let mut seen = HashSet::new();
+
+    match keys.into_iter().find(|key| !seen.insert(*key)) {
+        Some(key) => Err(Error::InvalidSpec(format!(
+            "{} has more than one {:?}",
+            field, key
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Parameters the operator sets itself, from the spec, credentials and authorization in progress,
+/// which an API's or connection's parameters would otherwise silently replace
+const RESERVED_PARAMS: [&str; 7] = [
+    "scope",
+    "client_id",
+    "redirect_uri",
+    "state",
+    "response_type",
+    "grant_type",
+    "code",
+];
+
+/// Prefix of the PKCE parameters, which the operator also sets itself
+const RESERVED_PARAM_PREFIX: &str = "code_challenge";
+
+fn is_reserved_param(key: &str) -> bool {
+    RESERVED_PARAMS.contains(&key) || key.starts_with(RESERVED_PARAM_PREFIX)
+}
+
+/// Checks that none of the parameters is one the operator sets itself. Before Kubernetes 1.25 the
+/// schema's rule isn't enforced.
+pub(crate) fn validate_unreserved_params(field: &str, params: &[AuthorizationParams]) -> Result<()> {
+    match params.iter().find(|param| is_reserved_param(&param.key)) {
+        Some(param) => Err(Error::InvalidSpec(format!(
+            "{} must not set {:?}, which the operator sets itself",
+            field, param.key
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// CEL condition that `key` isn't a parameter the operator sets itself
+pub(crate) fn unreserved_param_rule(key: &str) -> String {
+    format!(
+        "!({} in [{}]) && !{}.startsWith('{}')",
+        key,
+        RESERVED_PARAMS
+            .iter()
+            .map(|param| format!("'{}'", param))
+            .collect::<Vec<_>>()
+            .join(", "),
+        key,
+        RESERVED_PARAM_PREFIX
+    )
+}
+
+/// Parameters, none of which the operator sets itself, bounded like headers
+pub(crate) fn unreserved_params(gen: &mut SchemaGenerator) -> Schema {
+    let mut schema = schema::with_rules::<Vec<AuthorizationParams>>(gen, &[(
+        &format!("self.all(p, {})", unreserved_param_rule("p.key")),
+        "must not set parameters the operator sets itself",
+    )])
+    .into_object();
+    schema.array().max_items = Some(32);
+
+    Schema::Object(schema)
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct HttpApi {
+    #[schemars(url)]
     pub base_url: String,
-    pub authorization_header_prefix: Option<String>,
 
+    /// Precedes the token in the `Authorization` header
+    #[serde(default = "default_authorization_header_prefix")]
+    pub authorization_header_prefix: String,
+
+    /// Sent with every request to the API
     #[serde(default)]
+    #[schemars(schema_with = "unique_headers")]
     pub headers: Vec<HttpHeaders>,
+
+    pub identity: Option<IdentitySpec>,
+}
+
+impl HttpApi {
+    /// Value of the `Authorization` header carrying `token`; an empty prefix sends the bare token
+    pub fn authorization_header(&self, token: &str) -> String {
+        match self.authorization_header_prefix.as_str() {
+            "" => token.to_string(),
+            prefix => format!("{} {}", prefix, token),
+        }
+    }
+}
+
+pub(super) fn default_authorization_header_prefix() -> String {
+    String::from("Bearer")
+}
+
+// Bounded, so that the API server's estimated cost of the rule stays within its budget
+fn unique_headers(gen: &mut SchemaGenerator) -> Schema {
+    let mut schema = schema::with_rules::<Vec<HttpHeaders>>(gen, &[(
+        "self.all(h, self.exists_one(o, o.key == h.key))",
+        "header keys must be unique",
+    )])
+    .into_object();
+    schema.array().max_items = Some(32);
+
+    Schema::Object(schema)
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct HttpHeaders {
-    key: String,
-    value: String,
+    #[schemars(length(min = 1, max = 256))]
+    pub key: String,
+    pub value: String,
+}
+
+/// Describes how to discover who authorized a connection, once a token is available
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentitySpec {
+    /// Path, relative to `baseUrl`, of an endpoint describing the authenticated account
+    #[schemars(length(min = 1))]
+    pub path: String,
+
+    /// JSON pointer to the identity within the response, e.g. `/login`
+    #[schemars(regex(pattern = r"^(/.*)?$"))]
+    pub pointer: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
@@ -57,6 +276,7 @@ pub enum OAuthApiPhase {
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct OAuthApiStatus {
     pub phase: Option<OAuthApiPhase>,
 }
@@ -70,33 +290,262 @@ pub enum AuthSpecs {
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct OAuth2Spec {
+    #[schemars(url)]
     pub authorization_url: String,
+    /// Sent with every authorization request, unless a connection sets the same parameter
     #[serde(default)]
+    #[schemars(schema_with = "unreserved_params")]
     pub authorization_params: Vec<AuthorizationParams>,
 
+    /// Requested by connections which don't list any scopes of their own
+    #[serde(default)]
+    pub default_scopes: Vec<String>,
+    /// Requested by every connection, alongside its own scopes
+    #[serde(default)]
+    pub required_scopes: Vec<String>,
+
+    /// Joins the requested scopes, and splits those granted
+    #[serde(default = "default_scope_separator")]
+    #[schemars(length(min = 1))]
+    pub scope_separator: String,
+    /// Prepended to each requested scope not already starting with it, and removed from those
+    /// granted, such as Google's `https://www.googleapis.com/auth/`
+    pub scope_prefix: Option<String>,
+
+    #[schemars(url)]
     pub refresh_url: Option<String>,
 
+    #[schemars(url)]
     pub token_url: String,
     pub token_params: Option<TokenParams>,
+
+    /// How the client authenticates itself to the token endpoint
+    #[serde(default)]
+    pub token_endpoint_auth_method: TokenEndpointAuthMethod,
+
+    /// Sent with every token request, unless a connection sets the same parameter
+    #[serde(default)]
+    #[schemars(schema_with = "unreserved_params")]
+    pub extra_token_params: Vec<AuthorizationParams>,
+
+    /// Where to find the token in responses from the token endpoint, for APIs whose responses
+    /// don't follow the standard
+    pub token_response: Option<TokenResponseMapping>,
+}
+
+pub(super) fn default_scope_separator() -> String {
+    String::from(" ")
 }
 
 impl OAuth2Spec {
-    pub fn get_authorization_params(&self) -> String {
-        self.authorization_params.iter().fold(String::from(""), |acc, x| {
-            format!("{}&{}={}", acc, x.key, x.value)
-        })
+    /// The `scope` parameter requesting `scopes`
+    pub fn scope_param(&self, scopes: &[String]) -> String {
+        scopes
+            .iter()
+            .map(|scope| match &self.scope_prefix {
+                Some(prefix) if !scope.starts_with(prefix.as_str()) => format!("{}{}", prefix, scope),
+                _ => scope.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(&self.scope_separator)
+    }
+
+    /// The scopes of a granted `scope` parameter, named as they were requested
+    pub fn granted_scopes(&self, scope_param: &str) -> Vec<String> {
+        scope_param
+            .split(self.scope_separator.as_str())
+            .map(str::trim)
+            .filter(|scope| !scope.is_empty())
+            .map(|scope| match &self.scope_prefix {
+                Some(prefix) => scope.strip_prefix(prefix.as_str()).unwrap_or(scope).to_string(),
+                None => scope.to_string(),
+            })
+            .collect()
     }
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct AuthorizationParams {
-    key: String,
-    value: String,
+    #[schemars(length(min = 1))]
+    pub key: String,
+    pub value: String,
+}
+
+/// Client authentication methods at the token endpoint, named as OpenID Connect names them
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, JsonSchema)]
+pub enum TokenEndpointAuthMethod {
+    /// The client ID and secret in an HTTP Basic `Authorization` header
+    #[default]
+    #[serde(rename = "client_secret_basic")]
+    ClientSecretBasic,
+    /// The client ID and secret as parameters of the request body
+    #[serde(rename = "client_secret_post")]
+    ClientSecretPost,
+    /// A JWT signed with the private key from the connection's credentials Secret
+    #[serde(rename = "private_key_jwt")]
+    PrivateKeyJwt,
+    /// No authentication, for public clients, which prove they started the authorization with PKCE
+    #[serde(rename = "none")]
+    None,
+}
+
+/// Locates the token's fields in a token response. Form encoded responses are read as a JSON object
+/// of their parameters.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenResponseMapping {
+    /// Format of the response body, which is otherwise taken from its `Content-Type`
+    pub content_type: Option<TokenContentType>,
+
+    /// JSON pointer to the access token, `/access_token` by default
+    #[schemars(regex(pattern = r"^(/.*)?$"))]
+    pub access_token_pointer: Option<String>,
+    /// JSON pointer to the refresh token, `/refresh_token` by default
+    #[schemars(regex(pattern = r"^(/.*)?$"))]
+    pub refresh_token_pointer: Option<String>,
+    /// JSON pointer to the access token's lifetime in seconds, `/expires_in` by default
+    #[schemars(regex(pattern = r"^(/.*)?$"))]
+    pub expires_in_pointer: Option<String>,
+    /// JSON pointer to the granted scopes, `/scope` by default
+    #[schemars(regex(pattern = r"^(/.*)?$"))]
+    pub scope_pointer: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+pub enum TokenContentType {
+    #[serde(rename = "application/json")]
+    Json,
+    #[serde(rename = "application/x-www-form-urlencoded")]
+    Form,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct TokenParams {
-    grant_type: String,
+    #[schemars(length(min = 1))]
+    pub grant_type: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn oauth_api(spec: serde_json::Value) -> OAuthApi {
+        OAuthApi::new("github", serde_json::from_value(spec).unwrap())
+    }
+
+    fn spec(headers: serde_json::Value, authorization_params: serde_json::Value) -> serde_json::Value {
+        json!({
+            "http": { "baseUrl": "https://api.github.com/", "headers": headers },
+            "auth": {
+                "oAuth2": {
+                    "authorizationUrl": "https://github.com/login/oauth/authorize",
+                    "authorizationParams": authorization_params,
+                    "tokenUrl": "https://github.com/login/oauth/access_token",
+                },
+            },
+        })
+    }
+
+    #[test]
+    fn accepts_unique_keys() {
+        let api = oauth_api(spec(
+            json!([{ "key": "Accept", "value": "application/json" }]),
+            json!([{ "key": "allow_signup", "value": "false" }]),
+        ));
+
+        assert!(api.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_duplicate_keys_which_v2_would_collapse() {
+        let headers = oauth_api(spec(
+            json!([
+                { "key": "Accept", "value": "application/json" },
+                { "key": "Accept", "value": "text/plain" },
+            ]),
+            json!([]),
+        ));
+        assert!(matches!(headers.validate(), Err(Error::InvalidSpec(_))));
+
+        let params = oauth_api(spec(
+            json!([]),
+            json!([
+                { "key": "prompt", "value": "consent" },
+                { "key": "prompt", "value": "none" },
+            ]),
+        ));
+        assert!(matches!(params.validate(), Err(Error::InvalidSpec(_))));
+    }
+
+    #[test]
+    fn rejects_params_the_operator_sets() {
+        for key in ["scope", "client_id", "response_type", "code_challenge"] {
+            let api = oauth_api(spec(json!([]), json!([{ "key": key, "value": "x" }])));
+            assert!(matches!(api.validate(), Err(Error::InvalidSpec(_))), "{}", key);
+        }
+
+        let mut token_params = spec(json!([]), json!([]));
+        token_params["auth"]["oAuth2"]["extraTokenParams"] = json!([{ "key": "code", "value": "x" }]);
+        assert!(matches!(
+            oauth_api(token_params).validate(),
+            Err(Error::InvalidSpec(_))
+        ));
+    }
+
+    fn oauth2(scope_separator: Option<&str>, scope_prefix: Option<&str>) -> OAuth2Spec {
+        let mut spec = json!({
+            "authorizationUrl": "https://accounts.google.com/o/oauth2/v2/auth",
+            "tokenUrl": "https://oauth2.googleapis.com/token",
+            "scopePrefix": scope_prefix,
+        });
+        if let Some(scope_separator) = scope_separator {
+            spec["scopeSeparator"] = json!(scope_separator);
+        }
+
+        serde_json::from_value(spec).unwrap()
+    }
+
+    fn scopes(scopes: &[&str]) -> Vec<String> {
+        scopes.iter().map(|scope| scope.to_string()).collect()
+    }
+
+    #[test]
+    fn joins_and_splits_scopes_with_spaces_by_default() {
+        let spec = oauth2(None, None);
+
+        assert_eq!(spec.scope_param(&scopes(&["repo", "user"])), "repo user");
+        assert_eq!(spec.granted_scopes(" repo  user "), scopes(&["repo", "user"]));
+    }
+
+    #[test]
+    fn joins_and_splits_scopes_with_the_separator() {
+        let spec = oauth2(Some(","), None);
+
+        assert_eq!(
+            spec.scope_param(&scopes(&["identify", "email"])),
+            "identify,email"
+        );
+        assert_eq!(
+            spec.granted_scopes("identify, email,"),
+            scopes(&["identify", "email"])
+        );
+        assert!(spec.granted_scopes("").is_empty());
+    }
+
+    #[test]
+    fn prefixes_requested_scopes_and_unprefixes_granted_ones() {
+        let spec = oauth2(None, Some("https://www.googleapis.com/auth/"));
+
+        assert_eq!(
+            spec.scope_param(&scopes(&["youtube", "https://www.googleapis.com/auth/calendar"])),
+            "https://www.googleapis.com/auth/youtube https://www.googleapis.com/auth/calendar"
+        );
+        assert_eq!(
+            spec.granted_scopes("https://www.googleapis.com/auth/youtube openid"),
+            scopes(&["youtube", "openid"])
+        );
+    }
 }
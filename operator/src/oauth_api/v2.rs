@@ -0,0 +1,271 @@
+//! `chappaai.dev/v2` of `OAuthApi`, served alongside `v1` and converted to it for storage.
+//!
+//! Headers and authorization parameters become maps, and `auth` a struct discriminated by `type`
+//! rather than an externally tagged enum. Anything `v2` can express, `v1` can too. The reverse
+//! holds for `v1` objects with unique keys, which `OAuthApi::validate` requires, so conversion of
+//! admitted objects loses no data.
+
+use super::resource::{
+    self, default_authorization_header_prefix, default_scope_separator, AuthSpecs, AuthorizationParams,
+    HttpHeaders, IdentitySpec, OAuth2Spec, OAuthApiStatus, TokenEndpointAuthMethod, TokenParams,
+    TokenResponseMapping,
+};
+use crate::kubernetes::schema;
+use kube::CustomResource;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "chappaai.dev",
+    version = "v2",
+    kind = "OAuthApi",
+    status = "OAuthApiStatus",
+    shortname = "oapi",
+    category = "chappaai",
+    printcolumn = r#"{"name":"Status", "type":"string", "description":"registration status", "jsonPath":".status.phase"}"#,
+    printcolumn = r#"{"name":"Base URL", "type":"string", "description":"base URL of the API", "jsonPath":".spec.http.baseUrl"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#,
+    namespaced
+)]
+#[serde(rename_all = "camelCase")]
+pub struct OAuthApiSpec {
+    pub http: HttpApi,
+    pub auth: Option<AuthSpec>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpApi {
+    #[schemars(url)]
+    pub base_url: String,
+
+    /// Precedes the token in the `Authorization` header
+    #[serde(default = "default_authorization_header_prefix")]
+    pub authorization_header_prefix: String,
+
+    /// Sent with every request to the API
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+
+    pub identity: Option<IdentitySpec>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub enum AuthType {
+    OAuth2,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthSpec {
+    #[serde(rename = "type")]
+    pub type_: AuthType,
+
+    #[schemars(url)]
+    pub authorization_url: String,
+    /// Sent with every authorization request, unless a connection sets the same parameter
+    #[serde(default)]
+    #[schemars(schema_with = "unreserved_param_map")]
+    pub authorization_params: BTreeMap<String, String>,
+
+    /// Requested by connections which don't list any scopes of their own
+    #[serde(default)]
+    pub default_scopes: Vec<String>,
+    /// Requested by every connection, alongside its own scopes
+    #[serde(default)]
+    pub required_scopes: Vec<String>,
+
+    /// Joins the requested scopes, and splits those granted
+    #[serde(default = "default_scope_separator")]
+    #[schemars(length(min = 1))]
+    pub scope_separator: String,
+    /// Prepended to each requested scope not already starting with it, and removed from those
+    /// granted, such as Google's `https://www.googleapis.com/auth/`
+    pub scope_prefix: Option<String>,
+
+    #[schemars(url)]
+    pub refresh_url: Option<String>,
+
+    #[schemars(url)]
+    pub token_url: String,
+    pub token_params: Option<TokenParams>,
+
+    /// How the client authenticates itself to the token endpoint
+    #[serde(default)]
+    pub token_endpoint_auth_method: TokenEndpointAuthMethod,
+
+    /// Sent with every token request, unless a connection sets the same parameter
+    #[serde(default)]
+    #[schemars(schema_with = "unreserved_param_map")]
+    pub extra_token_params: BTreeMap<String, String>,
+
+    /// Where to find the token in responses from the token endpoint, for APIs whose responses
+    /// don't follow the standard
+    pub token_response: Option<TokenResponseMapping>,
+}
+
+/// Parameters, none of which the operator sets itself, bounded like their `v1` lists
+fn unreserved_param_map(gen: &mut SchemaGenerator) -> Schema {
+    let mut schema = schema::with_rules::<BTreeMap<String, String>>(gen, &[(
+        &format!("self.all(k, {})", resource::unreserved_param_rule("k")),
+        "must not set parameters the operator sets itself",
+    )])
+    .into_object();
+    schema.object().max_properties = Some(32);
+
+    Schema::Object(schema)
+}
+
+impl From<resource::OAuthApiSpec> for OAuthApiSpec {
+    fn from(spec: resource::OAuthApiSpec) -> Self {
+        OAuthApiSpec {
+            http: HttpApi {
+                base_url: spec.http.base_url,
+                authorization_header_prefix: spec.http.authorization_header_prefix,
+                headers: spec
+                    .http
+                    .headers
+                    .into_iter()
+                    .map(|header| (header.key, header.value))
+                    .collect(),
+                identity: spec.http.identity,
+            },
+            auth: spec.auth.map(|auth| match auth {
+                AuthSpecs::OAuth2(oauth2) => AuthSpec {
+                    type_: AuthType::OAuth2,
+                    authorization_url: oauth2.authorization_url,
+                    authorization_params: oauth2
+                        .authorization_params
+                        .into_iter()
+                        .map(|param| (param.key, param.value))
+                        .collect(),
+                    default_scopes: oauth2.default_scopes,
+                    required_scopes: oauth2.required_scopes,
+                    scope_separator: oauth2.scope_separator,
+                    scope_prefix: oauth2.scope_prefix,
+                    refresh_url: oauth2.refresh_url,
+                    token_url: oauth2.token_url,
+                    token_params: oauth2.token_params,
+                    token_endpoint_auth_method: oauth2.token_endpoint_auth_method,
+                    extra_token_params: oauth2
+                        .extra_token_params
+                        .into_iter()
+                        .map(|param| (param.key, param.value))
+                        .collect(),
+                    token_response: oauth2.token_response,
+                },
+            }),
+        }
+    }
+}
+
+impl From<OAuthApiSpec> for resource::OAuthApiSpec {
+    fn from(spec: OAuthApiSpec) -> Self {
+        resource::OAuthApiSpec {
+            http: resource::HttpApi {
+                base_url: spec.http.base_url,
+                authorization_header_prefix: spec.http.authorization_header_prefix,
+                headers: spec
+                    .http
+                    .headers
+                    .into_iter()
+                    .map(|(key, value)| HttpHeaders { key, value })
+                    .collect(),
+                identity: spec.http.identity,
+            },
+            auth: spec.auth.map(|auth| match auth.type_ {
+                AuthType::OAuth2 => AuthSpecs::OAuth2(OAuth2Spec {
+                    authorization_url: auth.authorization_url,
+                    authorization_params: auth
+                        .authorization_params
+                        .into_iter()
+                        .map(|(key, value)| AuthorizationParams { key, value })
+                        .collect(),
+                    default_scopes: auth.default_scopes,
+                    required_scopes: auth.required_scopes,
+                    scope_separator: auth.scope_separator,
+                    scope_prefix: auth.scope_prefix,
+                    refresh_url: auth.refresh_url,
+                    token_url: auth.token_url,
+                    token_params: auth.token_params,
+                    token_endpoint_auth_method: auth.token_endpoint_auth_method,
+                    extra_token_params: auth
+                        .extra_token_params
+                        .into_iter()
+                        .map(|(key, value)| AuthorizationParams { key, value })
+                        .collect(),
+                    token_response: auth.token_response,
+                }),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn v1() -> resource::OAuthApiSpec {
+        serde_json::from_value(json!({
+            "http": {
+                "baseUrl": "https://www.googleapis.com/",
+                "authorizationHeaderPrefix": "Bearer",
+                "headers": [
+                    { "key": "Accept", "value": "application/json" },
+                    { "key": "User-Agent", "value": "chappaai" },
+                ],
+                "identity": { "path": "oauth2/v2/userinfo", "pointer": "/email" },
+            },
+            "auth": {
+                "oAuth2": {
+                    "authorizationUrl": "https://accounts.google.com/o/oauth2/v2/auth",
+                    "authorizationParams": [
+                        { "key": "access_type", "value": "offline" },
+                        { "key": "prompt", "value": "consent" },
+                    ],
+                    "defaultScopes": ["userinfo.email"],
+                    "requiredScopes": ["openid"],
+                    "scopeSeparator": " ",
+                    "scopePrefix": "https://www.googleapis.com/auth/",
+                    "refreshUrl": "https://oauth2.googleapis.com/refresh",
+                    "tokenUrl": "https://oauth2.googleapis.com/token",
+                    "tokenParams": { "grantType": "authorization_code" },
+                    "tokenEndpointAuthMethod": "client_secret_post",
+                    "extraTokenParams": [{ "key": "audience", "value": "chappaai" }],
+                    "tokenResponse": { "contentType": "application/json", "accessTokenPointer": "/data/token" },
+                },
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn converts_v1_to_v2_and_back() {
+        let v2 = OAuthApiSpec::from(v1());
+
+        assert_eq!(v2.http.headers["User-Agent"], "chappaai");
+        assert_eq!(
+            v2.auth.as_ref().unwrap().authorization_params["prompt"],
+            "consent"
+        );
+
+        let v1_again = resource::OAuthApiSpec::from(v2);
+        assert_eq!(
+            serde_json::to_value(v1_again).unwrap(),
+            serde_json::to_value(v1()).unwrap()
+        );
+    }
+
+    #[test]
+    fn converts_v2_to_v1_and_back() {
+        let v2 = serde_json::to_value(OAuthApiSpec::from(v1())).unwrap();
+        let v2_again = OAuthApiSpec::from(resource::OAuthApiSpec::from(
+            serde_json::from_value::<OAuthApiSpec>(v2.clone()).unwrap(),
+        ));
+
+        assert_eq!(serde_json::to_value(v2_again).unwrap(), v2);
+    }
+}
@@ -1,152 +1,309 @@
-use std::{
-    collections::BTreeMap,
-    sync::Arc,
-    time::{Duration, SystemTime},
-};
+use std::sync::Arc;
 
-use super::OAuthConnection;
+use super::{
+    client_assertion, reload, storage, targets,
+    token::{self, StoredToken},
+    token_client, OAuthConnection,
+};
 use crate::{
-    api_version,
-    oauth_connection::{OAuthConnectionPhase, OAuthConnectionStatus},
-    ApplicationState, OAuthApi, Result,
+    api_version, authentication,
+    oauth_api::{IdentitySpec, TokenEndpointAuthMethod},
+    oauth_connection::{Condition, OAuthConnectionPhase, OAuthConnectionStatus},
+    ApplicationState, Error, OAuthApi, Result, WEB_API_VERSION,
 };
 
 use axum::{
-    extract::{Path, Query},
-    response::{IntoResponse, Redirect},
+    extract::{rejection::QueryRejection, Path, Query},
+    http::{
+        header::{self, HeaderName},
+        HeaderMap,
+    },
+    response::Redirect,
     Extension, Json,
 };
-use chrono::{DateTime, Utc};
-use hyper::StatusCode;
 use k8s_openapi::api::core::v1::Secret;
 use kube::{
     api::{Patch, PatchParams},
-    core::ObjectMeta,
+    runtime::events::{Event, EventType, Recorder},
     Api, Resource, ResourceExt,
 };
 
 use oauth2::{
-    basic::BasicClient, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, RedirectUrl, Scope,
-    TokenResponse, TokenUrl,
+    basic::BasicClient, AuthType, AuthUrl, AuthorizationCode, ClientId, ClientSecret, PkceCodeChallenge,
+    RedirectUrl, TokenResponse, TokenUrl,
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tracing::{info_span, instrument, warn, Instrument};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// JSON representation of an `OAuthConnection`, versioned by `WEB_API_VERSION`
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct OAuthConnectionWeb {
-    name: String,
-    phase: String,
+    pub api_version: String,
+    pub kind: String,
+    pub name: String,
+    pub namespace: Option<String>,
+    pub phase: String,
+    pub api: String,
+    pub requested_scopes: Vec<String>,
+    pub granted_scopes: Vec<String>,
+    pub expires_at: Option<String>,
+    pub secret_name: Option<String>,
+    pub identity: Option<String>,
+    pub conditions: Vec<Condition>,
+}
+
+impl From<&OAuthConnection> for OAuthConnectionWeb {
+    fn from(oauth_connection: &OAuthConnection) -> Self {
+        let status = oauth_connection.status.clone().unwrap_or_default();
+
+        OAuthConnectionWeb {
+            api_version: String::from(WEB_API_VERSION),
+            kind: String::from("OAuthConnection"),
+            name: oauth_connection.name(),
+            namespace: oauth_connection.namespace(),
+            phase: match &status.phase {
+                Some(phase) => phase.into(),
+                None => String::from("Status and phase not known"),
+            },
+            api: oauth_connection.spec.api.clone(),
+            requested_scopes: oauth_connection.spec.scopes.clone(),
+            granted_scopes: status.granted_scopes.unwrap_or_default(),
+            expires_at: status.expires_at,
+            secret_name: status.secret_name,
+            identity: status.identity,
+            conditions: status.conditions,
+        }
+    }
 }
 
+#[instrument(skip_all)]
 pub async fn list(
     Extension(state): Extension<Arc<ApplicationState>>,
 ) -> Result<Json<Vec<OAuthConnectionWeb>>> {
-    let oauth_connections = &state.oauth_connections.state(); // <- get app_name
-    let names: Vec<OAuthConnectionWeb> = oauth_connections
+    Ok(Json(web_connections(&state)))
+}
+
+pub(crate) fn web_connections(state: &ApplicationState) -> Vec<OAuthConnectionWeb> {
+    let oauth_connections = &state.oauth_connections.state();
+    oauth_connections
         .iter()
-        .map(|service| {
-            let meta = service.metadata.clone();
-
-            OAuthConnectionWeb {
-                name: meta.name.unwrap_or_else(|| String::from("Unknown")),
-                phase: match &service.status {
-                    Some(OAuthConnectionStatus {
-                        phase: Some(phase),
-                        secret_name: _,
-                        expires_at: _,
-                    }) => phase.into(),
-                    _ => String::from("Status and phase not known"),
-                },
-            }
-        })
-        .collect();
+        .map(|oauth_connection| OAuthConnectionWeb::from(oauth_connection.as_ref()))
+        .collect()
+}
+
+#[instrument(skip_all, fields(connection = %name))]
+pub async fn get(
+    Path(name): Path<String>,
+    Extension(state): Extension<Arc<ApplicationState>>,
+) -> Result<Json<OAuthConnectionWeb>> {
+    let oauth_connection = find_oauth_connection(&state, &name)?;
+
+    Ok(Json(OAuthConnectionWeb::from(oauth_connection.as_ref())))
+}
+
+/// A connection's current access token, as served to workloads
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OAuthTokenWeb {
+    pub api_version: String,
+    pub kind: String,
+    pub connection: String,
+    pub access_token: String,
+    pub authorization_header_prefix: String,
+    pub expires_at: Option<String>,
+}
 
-    Ok(Json(names))
+/// Serves the connection's token to a workload authenticated by its ServiceAccount token, and
+/// allowed by RBAC to `get` the connection's `token` subresource. Unlike the mounted Secret, the
+/// token is always current.
+#[instrument(skip_all, fields(connection = %name, caller))]
+pub async fn token(
+    Path(name): Path<String>,
+    Extension(state): Extension<Arc<ApplicationState>>,
+    headers: HeaderMap,
+) -> Result<([(HeaderName, &'static str); 1], Json<OAuthTokenWeb>)> {
+    let caller = authentication::authenticate(&state, &headers).await?;
+    tracing::Span::current().record("caller", &caller.username.as_str());
+
+    let (oac, oaa) = oauth_connection_and_api(&state, &name)?;
+    authentication::authorize(&state, &caller, &oac, "get").await?;
+
+    let token = token::current(&state, &oac, &oaa).await?;
+
+    Ok((
+        [(header::CACHE_CONTROL, "no-store")],
+        Json(OAuthTokenWeb {
+            api_version: String::from(WEB_API_VERSION),
+            kind: String::from("OAuthToken"),
+            connection: name,
+            access_token: token.access_token,
+            authorization_header_prefix: oaa.spec.http.authorization_header_prefix.clone(),
+            expires_at: token.expires_at.map(|expires_at| expires_at.to_rfc3339()),
+        }),
+    ))
 }
 
+#[instrument(skip_all, fields(connection = %name))]
 pub async fn connect(
-    Query(query): Query<OAuthRequest>,
+    query: Result<Query<OAuthRequest>, QueryRejection>,
     Path(name): Path<String>,
     Extension(state): Extension<Arc<ApplicationState>>,
-) -> impl IntoResponse {
-    let oauth_connection_name = name;
-
-    let (oac, oaa) = match oauth_connection_and_api(
-        oauth_connection_name,
-        state.oauth_connections.state(),
-        state.oauth_apis.state(),
-    ) {
-        Some(result) => result,
-        None => {
-            return StatusCode::NOT_FOUND.into_response();
-        }
+) -> Result<Redirect> {
+    let Query(query) = query.map_err(|rejection| Error::InvalidRequest(rejection.to_string()))?;
+
+    let (oac, oaa) = oauth_connection_and_api(&state, &name)?;
+
+    let secrets: Api<Secret> = match oac.namespace() {
+        Some(namespace) => Api::namespaced(state.client.clone(), &namespace),
+        None => Api::default_namespaced(state.client.clone()),
     };
 
-    let client = state.client.clone();
-    let secrets: Api<Secret> = Api::default_namespaced(client.clone());
+    let (oauth_client, _) = oauth_basic_client(
+        secrets,
+        &oac,
+        &oaa,
+        oaa.get_token_url()?,
+        Some(query.redirect_url.clone()),
+    )
+    .await?;
 
-    let oauth_client = match oauth_basic_client(secrets.clone(), &oac, &oaa, query.redirect_url.clone()).await
-    {
-        Ok(c) => c,
-        Err(error) => {
-            println!("Returning 404 because: {:?}", error);
-            return StatusCode::NOT_FOUND.into_response();
+    // Public clients have no secret to prove that they started the authorization they complete
+    let (pkce_challenge, pkce_verifier) = match oaa.token_endpoint_auth_method() {
+        TokenEndpointAuthMethod::None => {
+            let (challenge, verifier) = PkceCodeChallenge::new_random_sha256();
+            (Some(challenge), Some(verifier))
         }
+        _ => (None, None),
+    };
+
+    let csrf_token = state.authorizations.start(&name, pkce_verifier)?;
+    let oauth_client = oauth_client.authorize_url(|| csrf_token);
+    let oauth_client = match pkce_challenge {
+        Some(challenge) => oauth_client.set_pkce_challenge(challenge),
+        None => oauth_client,
     };
 
-    let oauth_client = oauth_client.authorize_url(|| CsrfToken::new(String::from("abc")));
+    // Joined here, rather than with `add_scope`, which always separates scopes with spaces
+    let scopes = oac.requested_scopes(&oaa);
+    let oauth_client = match oaa.oauth2() {
+        Some(spec) if !scopes.is_empty() => oauth_client.add_extra_param("scope", spec.scope_param(&scopes)),
+        _ => oauth_client,
+    };
 
-    let oauth_client = oac.spec.scopes.iter().fold(oauth_client, |client, scope| {
-        client.add_scope(Scope::new(scope.clone()))
-    });
+    let oauth_client = oac
+        .authorization_params(&oaa)
+        .into_iter()
+        .fold(oauth_client, |client, (key, value)| {
+            client.add_extra_param(key, value)
+        });
 
     let (auth_url, _csrf_token) = oauth_client.url();
 
-    Redirect::temporary(auth_url.as_ref()).into_response()
+    Ok(Redirect::temporary(auth_url.as_ref()))
 }
 
-async fn oauth_basic_client(
+/// An OAuth client for the connection, exchanging tokens at `token_url`, and the parameters which
+/// authenticate it in token requests, for methods `oauth2` doesn't implement
+pub(crate) async fn oauth_basic_client(
     secrets: Api<Secret>,
     oac: &OAuthConnection,
     oaa: &OAuthApi,
-    redirect_url: String,
-) -> Result<oauth2::basic::BasicClient> {
-    let (client_id, client_secret) = oac.load_client_keys(secrets).await?;
+    token_url: String,
+    redirect_url: Option<String>,
+) -> Result<(oauth2::basic::BasicClient, Vec<(String, String)>)> {
+    if oaa.spec.auth.is_none() {
+        return Err(Error::InvalidRequest(format!(
+            "OAuthApi {} has no auth",
+            oaa.name()
+        )));
+    }
+
+    let credentials = match oac.load_client_credentials(secrets).await {
+        Ok(credentials) => credentials,
+        Err(Error::KubeError(kube::Error::Api(response))) if response.code == 404 => {
+            return Err(Error::CredentialsMissing(response.message))
+        }
+        Err(Error::GenericError(message)) => return Err(Error::CredentialsMissing(message)),
+        Err(error) => return Err(error),
+    };
+
+    let method = oaa.token_endpoint_auth_method();
+    let client_secret = match method {
+        TokenEndpointAuthMethod::ClientSecretBasic | TokenEndpointAuthMethod::ClientSecretPost => Some(
+            credentials
+                .client_secret
+                .map(ClientSecret::new)
+                .ok_or_else(|| Error::CredentialsMissing(String::from("Secret has no client secret")))?,
+        ),
+        TokenEndpointAuthMethod::PrivateKeyJwt | TokenEndpointAuthMethod::None => None,
+    };
+
+    let auth_params = match (method, &credentials.private_key) {
+        (TokenEndpointAuthMethod::PrivateKeyJwt, Some(private_key)) => {
+            client_assertion::params(private_key, &credentials.client_id, &token_url)?
+        }
+        (TokenEndpointAuthMethod::PrivateKeyJwt, None) => {
+            return Err(Error::CredentialsMissing(String::from(
+                "Secret has no private key",
+            )))
+        }
+        _ => vec![],
+    };
 
-    let auth_url = AuthUrl::new(oaa.get_authorization_url()).unwrap();
-    let token_url = TokenUrl::new(oaa.get_token_url()).unwrap();
+    let auth_url = AuthUrl::new(oaa.get_authorization_url()?)
+        .map_err(|e| Error::InvalidRequest(format!("Invalid authorization URL: {}", e)))?;
+    let token_url =
+        TokenUrl::new(token_url).map_err(|e| Error::InvalidRequest(format!("Invalid token URL: {}", e)))?;
 
-    Ok(BasicClient::new(
-        ClientId::new(client_id),
-        Some(ClientSecret::new(client_secret)),
+    // In the request body, `oauth2` sends the client ID, and the secret only when there is one
+    let client = BasicClient::new(
+        ClientId::new(credentials.client_id),
+        client_secret,
         auth_url,
         Some(token_url),
     )
-    .set_redirect_uri(RedirectUrl::new(redirect_url).unwrap()))
-}
+    .set_auth_type(match method {
+        TokenEndpointAuthMethod::ClientSecretBasic => AuthType::BasicAuth,
+        _ => AuthType::RequestBody,
+    });
 
-fn oauth_connection_and_api(
-    oauth_connection_name: String,
-    oacs: Vec<Arc<OAuthConnection>>,
-    oaas: Vec<Arc<OAuthApi>>,
-) -> Option<(OAuthConnection, OAuthApi)> {
-    let oac: &OAuthConnection = match oacs
-        .iter()
-        .find(|c| c.metadata.name.clone().unwrap_or_else(|| String::from("Unknown")) == oauth_connection_name)
-    {
-        Some(c) => c,
-        None => return None,
+    let client = match redirect_url {
+        Some(redirect_url) => client.set_redirect_uri(
+            RedirectUrl::new(redirect_url)
+                .map_err(|e| Error::InvalidRequest(format!("Invalid redirect_url: {}", e)))?,
+        ),
+        None => client,
     };
 
-    let oaa: &OAuthApi = match oaas
-        .iter()
-        .find(|c| c.metadata.name.clone().unwrap_or_else(|| String::from("Unknown")) == oac.spec.api)
-    {
-        Some(c) => c,
-        None => return None,
-    };
+    Ok((client, auth_params))
+}
+
+fn find_oauth_connection(state: &ApplicationState, name: &str) -> Result<Arc<OAuthConnection>> {
+    state
+        .oauth_connections
+        .state()
+        .into_iter()
+        .find(|oauth_connection| oauth_connection.name() == name)
+        .ok_or_else(|| Error::ConnectionNotFound(name.to_string()))
+}
+
+pub(crate) fn oauth_connection_and_api(
+    state: &ApplicationState,
+    name: &str,
+) -> Result<(OAuthConnection, OAuthApi)> {
+    let oac = find_oauth_connection(state, name)?;
 
-    Some((oac.to_owned(), oaa.to_owned()))
+    let oaa = state
+        .oauth_apis
+        .state()
+        .into_iter()
+        .find(|oaa| oaa.name() == oac.spec.api)
+        .ok_or_else(|| Error::ApiNotFound(oac.spec.api.clone()))?;
+
+    Ok((oac.as_ref().to_owned(), oaa.as_ref().to_owned()))
 }
 
 #[derive(Deserialize)]
@@ -156,33 +313,60 @@ pub struct OAuthRequest {
 
 #[derive(Deserialize)]
 pub struct OAuthResponse {
-    code: String,
-    #[allow(dead_code)]
-    state: String,
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
     redirect_url: String,
 }
 
+#[instrument(skip_all, fields(connection = %name))]
 pub async fn callback(
-    Query(query): Query<OAuthResponse>,
+    query: Result<Query<OAuthResponse>, QueryRejection>,
     Path(name): Path<String>,
     Extension(state): Extension<Arc<ApplicationState>>,
-) -> impl IntoResponse {
-    // let csrf_check = CsrfToken::new(result.state.clone());
-    let auth = AuthorizationCode::new(query.code.clone());
-
-    let oauth_connection_name = name;
-
-    let (oac, oaa) = match oauth_connection_and_api(
-        oauth_connection_name.clone(),
-        state.oauth_connections.state(),
-        state.oauth_apis.state(),
-    ) {
-        Some(result) => result,
-        None => {
-            return StatusCode::NOT_FOUND.into_response();
+) -> Result<&'static str> {
+    let Query(query) = query.map_err(|rejection| Error::InvalidRequest(rejection.to_string()))?;
+
+    let code = match (query.code, query.error) {
+        (Some(code), None) => code,
+        (_, Some(error)) => {
+            return Err(Error::AuthorizationDenied(
+                query.error_description.unwrap_or(error),
+            ))
         }
+        (None, None) => return Err(Error::InvalidRequest(String::from("missing field `code`"))),
     };
 
+    complete_authorization(
+        &state,
+        name,
+        code,
+        query.state.unwrap_or_default(),
+        query.redirect_url,
+    )
+    .await?;
+
+    Ok("Connected")
+}
+
+/// Exchanges an authorization code for a token, storing it within a Secret and
+/// moving the `OAuthConnection` to `Connected`.
+pub(crate) async fn complete_authorization(
+    state: &ApplicationState,
+    oauth_connection_name: String,
+    code: String,
+    csrf_state: String,
+    redirect_url: String,
+) -> Result<()> {
+    let pkce_verifier = state
+        .authorizations
+        .complete(&csrf_state, &oauth_connection_name)?;
+
+    let auth = AuthorizationCode::new(code);
+
+    let (oac, oaa) = oauth_connection_and_api(state, &oauth_connection_name)?;
+
     let name = oac.name();
     let namespace = oac.namespace();
 
@@ -199,89 +383,255 @@ pub async fn callback(
         ),
     };
 
-    let oauth_client = match oauth_basic_client(secrets.clone(), &oac, &oaa, query.redirect_url.clone()).await
-    {
-        Ok(c) => c,
-        Err(error) => {
-            println!("Returning 404 because: {:?}", error);
-            return StatusCode::NOT_FOUND.into_response();
-        }
+    let (oauth_client, auth_params) = oauth_basic_client(
+        secrets.clone(),
+        &oac,
+        &oaa,
+        oaa.get_token_url()?,
+        Some(redirect_url),
+    )
+    .await?;
+
+    let request = match pkce_verifier {
+        Some(pkce_verifier) => oauth_client.exchange_code(auth).set_pkce_verifier(pkce_verifier),
+        None => oauth_client.exchange_code(auth),
     };
 
-    let token = match oauth_client
-        .exchange_code(auth)
-        .request_async(oauth2::reqwest::async_http_client)
-        .await
-    {
-        Ok(t) => t,
+    let token = auth_params
+        .into_iter()
+        .chain(oac.extra_token_params(&oaa))
+        .fold(request, |request, (key, value)| {
+            request.add_extra_param(key, value)
+        })
+        .request_async(|request| token_client::http_client(oaa.token_response(), request))
+        .instrument(info_span!("token_exchange", api = %oaa.name(), connection = %name))
+        .await;
+    state.metrics.token_exchange(&oaa.name(), token.is_ok());
+    let token = token.map_err(|e| Error::TokenExchangeFailed(token_client::describe(&e)))?;
+
+    let recorder = Recorder::new(state.client.clone(), state.reporter.clone(), oac.object_ref(&()));
+
+    let storage = storage::for_connection(&state.client, &state.config, &oac);
+    let stored_token = StoredToken::from_response(&token, None);
+
+    // Held until the status is written or the token rolled back, so that a refresh in flight can't
+    // overwrite the new token with one refreshed from the old
+    let lock = state.refreshes.lock(&oac);
+    let _guard = lock.lock().await;
+
+    // Kept so that a failed status update can restore the token we are about to replace. One which
+    // can't be read, such as after its encryption key was removed, is what reconnecting replaces, and
+    // mustn't waste the code which has already been exchanged.
+    let previous_token = match storage.load(&oac).await {
+        Ok(previous_token) => previous_token,
         Err(e) => {
-            return (StatusCode::UNAUTHORIZED, format!("Failed: {:?}", e)).into_response();
+            warn!("Replacing the token of {}, which can't be read: {}", name, e);
+            None
         }
     };
 
-    let secret_name = format!("chappaai-{}", name);
-    let owner_ref = oac.controller_owner_ref(&()).unwrap();
-
-    let mut string_data: BTreeMap<String, String> = BTreeMap::new();
-    string_data.insert(
-        "accessToken".to_string(),
-        token.access_token().secret().to_string(),
-    );
-
-    let new_secret = Secret {
-        metadata: ObjectMeta {
-            name: Some(secret_name.clone()),
-            namespace: namespace.clone(),
-            owner_references: Some(vec![owner_ref]),
-            ..ObjectMeta::default()
-        },
-        immutable: Some(false),
-        string_data: Some(string_data),
-        ..Secret::default()
+    if let Err(e) = storage.store(&oac, &stored_token).await {
+        publish_failure(&recorder, "Failed to store token", e.to_string()).await;
+        return Err(e);
+    }
+
+    // Providers only return the granted scopes when they differ from those requested, and `oauth2`
+    // splits them on spaces whatever the API's separator
+    let granted_scopes = match (token.scopes(), oaa.oauth2()) {
+        (Some(scopes), Some(spec)) => spec.granted_scopes(
+            &scopes
+                .iter()
+                .map(|scope| scope.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        (Some(scopes), None) => scopes.iter().map(|scope| scope.to_string()).collect(),
+        (None, _) => oac.requested_scopes(&oaa),
     };
 
-    let _ = match secrets
-        .patch(
-            secret_name.clone().as_str(),
-            &PatchParams::apply("chappaai"),
-            &Patch::Apply(new_secret),
-        )
-        .await
-    {
-        Ok(_) => true,
-        Err(e) => {
-            panic!("Failed: {:?}", e);
-        }
+    let identity = match &oaa.spec.http.identity {
+        Some(identity) => fetch_identity(&state.http, &oaa, identity, token.access_token().secret()).await,
+        None => None,
     };
 
-    let duration = token.expires_in().unwrap_or(Duration::from_secs(0));
-    let time = SystemTime::now() + duration;
-    let datetime: DateTime<Utc> = time.into();
-
     let new_status = Patch::Apply(json!({
                 "apiVersion": api_version(),
                 "kind": "OAuthConnection",
                 "status": OAuthConnectionStatus {
                   phase: Some(OAuthConnectionPhase::Connected),
-                  secret_name: Some(secret_name.clone()),
-                  expires_at: Some(datetime.to_rfc3339()),
+                  secret_name: storage.secret_name(&oac),
+                  expires_at: stored_token.expires_at.map(|expires_at| expires_at.to_rfc3339()),
+                  granted_scopes: Some(granted_scopes),
+                  identity,
+                  conditions: vec![Condition::ready(true, "Connected", "Token available")],
                 }
     }));
 
-    let patch_params = PatchParams::apply("chappaai").force();
+    let patch_params = PatchParams::apply(&state.config.field_manager).force();
+
+    if let Err(e) = api.patch_status(&name, &patch_params, &new_status).await {
+        let rollback = match &previous_token {
+            Some(previous_token) => storage.store(&oac, previous_token).await,
+            None => storage.delete(&oac).await,
+        };
+
+        let note = match rollback {
+            Ok(_) => format!("{}. Token rolled back", e),
+            Err(rollback_error) => format!("{}. Rolling back token also failed: {}", e, rollback_error),
+        };
+
+        publish_failure(&recorder, "Failed to update status", note).await;
+        return Err(Error::KubeError(e));
+    }
 
-    let _ = match api
-        .patch_status(&name, &patch_params, &new_status)
+    // Copies and workloads the controller fails to update here are updated when it next reconciles
+    if let Err(e) = targets::replicate(&state.client, &state.config, &oac, &stored_token).await {
+        publish_failure(&recorder, "Failed to copy token", e.to_string()).await;
+    }
+    if let Err(e) = reload::reload(&state.client, &oac, &stored_token).await {
+        publish_failure(&recorder, "Failed to reload workloads", e.to_string()).await;
+    }
+
+    Ok(())
+}
+
+/// Records a failed authorization as a Warning event, logging rather than masking the original failure
+async fn publish_failure(recorder: &Recorder, reason: &str, note: String) {
+    warn!("{}: {}", reason, note);
+
+    if let Err(e) = recorder
+        .publish(Event {
+            type_: EventType::Warning,
+            reason: format!("❌ {}", reason),
+            note: Some(note),
+            action: "Connecting".into(),
+            secondary: None,
+        })
         .await
-        .map_err(crate::Error::KubeError)
     {
-        Ok(_) => true,
-        Err(e) => {
-            println!("Failed: {:?}", e);
+        warn!("Failed to publish event: {:?}", e);
+    }
+}
+
+/// Looks up the identity of the account behind a freshly issued token.
+///
+/// Failures are logged rather than returned, as the identity is informational only.
+#[instrument(skip_all, fields(api = %oaa.name()))]
+async fn fetch_identity(
+    http: &reqwest::Client,
+    oaa: &OAuthApi,
+    identity: &IdentitySpec,
+    access_token: &str,
+) -> Option<String> {
+    let url = format!(
+        "{}/{}",
+        oaa.spec.http.base_url.trim_end_matches('/'),
+        identity.path.trim_start_matches('/')
+    );
+
+    let request = oaa
+        .spec
+        .http
+        .headers
+        .iter()
+        .fold(http.get(&url), |request, header| {
+            request.header(&header.key, &header.value)
+        })
+        .header("Authorization", oaa.spec.http.authorization_header(access_token));
+
+    let body: serde_json::Value = match request.send().await.and_then(|r| r.error_for_status()) {
+        Ok(response) => match response.json().await {
+            Ok(body) => body,
+            Err(error) => {
+                warn!("Identity response from {} was not JSON: {:?}", url, error);
+                return None;
+            }
+        },
+        Err(error) => {
+            warn!("Failed to fetch identity from {}: {:?}", url, error);
+            return None;
+        }
+    };
 
-            false
+    match body.pointer(&identity.pointer) {
+        Some(serde_json::Value::String(value)) => Some(value.clone()),
+        Some(value) => Some(value.to_string()),
+        None => {
+            warn!("Identity response from {} has no {}", url, identity.pointer);
+            None
         }
+    }
+}
+
+/// Revokes the connection's token for a caller allowed by RBAC to `delete` its `token` subresource
+#[instrument(skip_all, fields(connection = %name, caller))]
+pub async fn disconnect(
+    Path(name): Path<String>,
+    Extension(state): Extension<Arc<ApplicationState>>,
+    headers: HeaderMap,
+) -> Result<&'static str> {
+    let caller = authentication::authenticate(&state, &headers).await?;
+    tracing::Span::current().record("caller", &caller.username.as_str());
+
+    let oac = find_oauth_connection(&state, &name)?;
+    authentication::authorize(&state, &caller, &oac, "delete").await?;
+
+    revoke_connection(&state, &name).await?;
+
+    Ok("Disconnected")
+}
+
+/// Removes the token of an `OAuthConnection`, and its copies, and moves it back to `Disconnected`.
+pub(crate) async fn revoke_connection(state: &ApplicationState, oauth_connection_name: &str) -> Result<()> {
+    let oac = find_oauth_connection(state, oauth_connection_name)?;
+
+    let name = oac.name();
+    let namespace = oac.namespace();
+
+    let api: Api<OAuthConnection> = match &namespace {
+        Some(namespace) => Api::namespaced(state.client.clone(), namespace),
+        None => Api::default_namespaced(state.client.clone()),
     };
 
-    "Connected".into_response()
+    // Waits for a refresh in progress, which would otherwise store a token after it's revoked
+    let lock = state.refreshes.lock(&oac);
+    let _guard = lock.lock().await;
+
+    storage::for_connection(&state.client, &state.config, &oac)
+        .delete(&oac)
+        .await?;
+    targets::prune(&state.client, &state.config, &oac, &[]).await?;
+
+    let new_status = Patch::Apply(json!({
+        "apiVersion": api_version(),
+        "kind": "OAuthConnection",
+        "status": OAuthConnectionStatus {
+            phase: Some(OAuthConnectionPhase::Disconnected),
+            conditions: vec![Condition::ready(false, "Disconnected", "Token revoked")],
+            ..OAuthConnectionStatus::default()
+        }
+    }));
+
+    api.patch_status(
+        &name,
+        &PatchParams::apply(&state.config.field_manager).force(),
+        &new_status,
+    )
+    .await
+    .map_err(Error::KubeError)?;
+
+    // Releases the expiry written by refreshes, which would otherwise outlive the token
+    api.patch_status(
+        &name,
+        &PatchParams::apply(&token::refresh_field_manager(&state.config)),
+        &Patch::Apply(json!({
+            "apiVersion": api_version(),
+            "kind": "OAuthConnection",
+            "status": {},
+        })),
+    )
+    .await
+    .map_err(Error::KubeError)?;
+
+    Ok(())
 }
@@ -0,0 +1,162 @@
+use crate::{config::Config, Error, Result};
+use chrono::Utc;
+use k8s_openapi::{api::core::v1::Secret, ByteString};
+use kube::{
+    api::{Api, ObjectMeta, PostParams},
+    Client,
+};
+use oauth2::{CsrfToken, PkceCodeVerifier};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, time::Duration};
+use tracing::info;
+
+/// How long a user has to complete the OAuth dance before the state is rejected
+const AUTHORIZATION_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Key of the state key in its Secret
+const STATE_KEY: &str = "key";
+
+const KEY_LEN: usize = 32;
+
+/// What the state sent to the provider holds, sealed so that it can be neither read nor forged
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PendingAuthorization {
+    expires_at: i64,
+    pkce_verifier: Option<String>,
+}
+
+/// Authorizations which have been started by `connect` but not yet completed by `callback`.
+///
+/// Nothing is remembered between the two: the state sent to the provider is sealed with a key
+/// shared by every replica, and bound to the connection, so whichever replica the callback reaches
+/// can complete it. Providers only exchange a code once, so a state can't be replayed.
+pub struct PendingAuthorizations {
+    key: LessSafeKey,
+}
+
+impl PendingAuthorizations {
+    /// Loads the state key shared by every replica from its Secret, in the operator's namespace,
+    /// generating it on first start. Replicas race to create the Secret and the losers use the
+    /// winner's.
+    pub async fn load_or_create(client: Client, config: &Config) -> Result<Self> {
+        let secrets: Api<Secret> = Api::default_namespaced(client);
+
+        let secret = match secrets.get_opt(&config.state_secret).await? {
+            Some(secret) => secret,
+            None => {
+                let mut key = vec![0u8; KEY_LEN];
+                SystemRandom::new()
+                    .fill(&mut key)
+                    .map_err(|_| Error::GenericError(String::from("Failed to generate a state key")))?;
+
+                let secret = Secret {
+                    metadata: ObjectMeta {
+                        name: Some(config.state_secret.clone()),
+                        ..ObjectMeta::default()
+                    },
+                    data: Some(BTreeMap::from([(String::from(STATE_KEY), ByteString(key))])),
+                    ..Secret::default()
+                };
+
+                match secrets.create(&PostParams::default(), &secret).await {
+                    Ok(secret) => {
+                        info!("Generated state key in Secret {}", config.state_secret);
+                        secret
+                    }
+                    Err(kube::Error::Api(response)) if response.code == 409 => {
+                        secrets.get(&config.state_secret).await?
+                    }
+                    Err(error) => return Err(Error::KubeError(error)),
+                }
+            }
+        };
+
+        let key = secret
+            .data
+            .unwrap_or_default()
+            .remove(STATE_KEY)
+            .filter(|key| key.0.len() == KEY_LEN)
+            .ok_or_else(|| {
+                Error::ConfigError(format!(
+                    "Secret {} must hold a {} byte {}",
+                    config.state_secret, KEY_LEN, STATE_KEY
+                ))
+            })?;
+
+        PendingAuthorizations::new(&key.0)
+    }
+
+    fn new(key: &[u8]) -> Result<Self> {
+        let key = UnboundKey::new(&AES_256_GCM, key)
+            .map_err(|_| Error::ConfigError(String::from("Invalid state key")))?;
+
+        Ok(PendingAuthorizations {
+            key: LessSafeKey::new(key),
+        })
+    }
+
+    /// Starts an authorization for the connection, sealing the PKCE verifier its code must be
+    /// exchanged with into the state to send to the provider
+    pub fn start(
+        &self,
+        oauth_connection: &str,
+        pkce_verifier: Option<PkceCodeVerifier>,
+    ) -> Result<CsrfToken> {
+        let authorization = PendingAuthorization {
+            expires_at: Utc::now().timestamp() + AUTHORIZATION_TTL.as_secs() as i64,
+            pkce_verifier: pkce_verifier.map(|verifier| verifier.secret().clone()),
+        };
+
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| Error::GenericError(String::from("Failed to generate a nonce")))?;
+
+        let mut sealed = serde_json::to_vec(&authorization).map_err(Error::SerializationError)?;
+        self.key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(oauth_connection.as_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| Error::GenericError(String::from("Failed to seal the state")))?;
+
+        let mut state = nonce.to_vec();
+        state.append(&mut sealed);
+
+        Ok(CsrfToken::new(base64::encode_config(
+            state,
+            base64::URL_SAFE_NO_PAD,
+        )))
+    }
+
+    /// Opens the state of an authorization, which must have been started for the same connection
+    /// and not have expired, returning its PKCE verifier
+    pub fn complete(&self, state: &str, oauth_connection: &str) -> Result<Option<PkceCodeVerifier>> {
+        let mut state =
+            base64::decode_config(state, base64::URL_SAFE_NO_PAD).map_err(|_| Error::StateMismatch)?;
+        if state.len() < NONCE_LEN {
+            return Err(Error::StateMismatch);
+        }
+
+        let mut sealed = state.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&state).map_err(|_| Error::StateMismatch)?;
+
+        let authorization = self
+            .key
+            .open_in_place(nonce, Aad::from(oauth_connection.as_bytes()), &mut sealed)
+            .ok()
+            .and_then(|opened| serde_json::from_slice::<PendingAuthorization>(opened).ok())
+            .ok_or(Error::StateMismatch)?;
+
+        match authorization.expires_at > Utc::now().timestamp() {
+            true => Ok(authorization.pkce_verifier.map(PkceCodeVerifier::new)),
+            false => Err(Error::StateMismatch),
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use super::OAuthConnection;
+use crate::{config::Config, Error};
+use k8s_openapi::api::rbac::v1::{PolicyRule, Role, RoleBinding, RoleRef, Subject};
+use kube::{
+    api::{Api, DeleteParams, Patch, PatchParams, PostParams, Preconditions},
+    core::ObjectMeta,
+    Client, Resource, ResourceExt,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Debug;
+
+const RBAC_API_GROUP: &str = "rbac.authorization.k8s.io";
+
+/// Grants the ServiceAccounts permitted by the connection's access policy `get` on its token
+/// Secret and `token` subresource, with a Role and RoleBinding named after the Secret. Both are
+/// owned by the connection, and removed once it no longer has an access policy. A Role or
+/// RoleBinding of that name which the connection doesn't own is never changed.
+pub async fn access(
+    client: Client,
+    config: &Config,
+    oauth_connection: &OAuthConnection,
+) -> Result<(), Error> {
+    let name = config.secret_name(&oauth_connection.name());
+    let namespace = oauth_connection.namespace();
+
+    let (roles, role_bindings): (Api<Role>, Api<RoleBinding>) = match &namespace {
+        Some(namespace) => (
+            Api::namespaced(client.clone(), namespace),
+            Api::namespaced(client, namespace),
+        ),
+        None => (
+            Api::default_namespaced(client.clone()),
+            Api::default_namespaced(client),
+        ),
+    };
+
+    let access = match &oauth_connection.spec.access {
+        Some(access) => access,
+        None => {
+            // Without a Role of ours, access was never granted, or has already been revoked
+            let role = match roles.get_opt(&name).await? {
+                Some(role) if owned_by(&role, oauth_connection) => role,
+                _ => return Ok(()),
+            };
+
+            if let Some(role_binding) = role_bindings.get_opt(&name).await? {
+                if owned_by(&role_binding, oauth_connection) {
+                    delete(&role_bindings, &role_binding).await?;
+                }
+            }
+
+            return delete(&roles, &role).await;
+        }
+    };
+
+    let owner_ref = oauth_connection.controller_owner_ref(&()).ok_or_else(|| {
+        Error::GenericError(format!("OAuthConnection {} has no uid", oauth_connection.name()))
+    })?;
+
+    let metadata = ObjectMeta {
+        name: Some(name.clone()),
+        namespace: namespace.clone(),
+        owner_references: Some(vec![owner_ref]),
+        ..ObjectMeta::default()
+    };
+
+    let role = Role {
+        metadata: metadata.clone(),
+        rules: Some(vec![
+            PolicyRule {
+                api_groups: Some(vec![String::new()]),
+                resources: Some(vec![String::from("secrets")]),
+                resource_names: Some(vec![name.clone()]),
+                verbs: vec![String::from("get")],
+                ..PolicyRule::default()
+            },
+            PolicyRule {
+                api_groups: Some(vec![String::from("chappaai.dev")]),
+                resources: Some(vec![String::from("oauthconnections/token")]),
+                resource_names: Some(vec![oauth_connection.name()]),
+                verbs: vec![String::from("get")],
+                ..PolicyRule::default()
+            },
+        ]),
+    };
+
+    let service_accounts = access.service_accounts.iter().map(|service_account| Subject {
+        kind: String::from("ServiceAccount"),
+        name: service_account.name.clone(),
+        namespace: service_account.namespace.clone().or_else(|| namespace.clone()),
+        api_group: None,
+    });
+
+    let namespaces = access.namespaces.iter().map(|namespace| Subject {
+        kind: String::from("Group"),
+        name: format!("system:serviceaccounts:{}", namespace),
+        namespace: None,
+        api_group: Some(String::from(RBAC_API_GROUP)),
+    });
+
+    let role_binding = RoleBinding {
+        metadata,
+        role_ref: RoleRef {
+            api_group: String::from(RBAC_API_GROUP),
+            kind: String::from("Role"),
+            name: name.clone(),
+        },
+        subjects: Some(service_accounts.chain(namespaces).collect()),
+    };
+
+    apply(config, oauth_connection, &roles, role).await?;
+    apply(config, oauth_connection, &role_bindings, role_binding).await
+}
+
+/// Whether the connection is the controller of `object`, and so created it
+fn owned_by<K: Resource>(object: &K, oauth_connection: &OAuthConnection) -> bool {
+    object.owner_references().iter().any(|owner| {
+        owner.controller == Some(true) && Some(&owner.uid) == oauth_connection.metadata.uid.as_ref()
+    })
+}
+
+/// Creates `object`, or updates it if the connection owns it. Updates are conditional on it being
+/// unchanged since it was found to be ours, so that RBAC created by anyone else is never taken over.
+async fn apply<K>(
+    config: &Config,
+    oauth_connection: &OAuthConnection,
+    api: &Api<K>,
+    mut object: K,
+) -> Result<(), Error>
+where
+    K: Resource<DynamicType = ()> + Clone + Serialize + DeserializeOwned + Debug,
+{
+    let name = object.name();
+
+    match api.get_opt(&name).await? {
+        None => {
+            let params = PostParams {
+                field_manager: Some(config.field_manager.clone()),
+                ..PostParams::default()
+            };
+            api.create(&params, &object).await?;
+        }
+        Some(existing) if owned_by(&existing, oauth_connection) => {
+            object.meta_mut().resource_version = existing.resource_version();
+            api.patch(
+                &name,
+                &PatchParams::apply(&config.field_manager),
+                &Patch::Apply(&object),
+            )
+            .await?;
+        }
+        Some(_) => {
+            return Err(Error::Forbidden(format!(
+                "{} {} already exists, and isn't owned by OAuthConnection {}",
+                K::kind(&()),
+                name,
+                oauth_connection.name()
+            )))
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes `object`, provided it is still the one found to be ours
+async fn delete<K>(api: &Api<K>, object: &K) -> Result<(), Error>
+where
+    K: Resource + Clone + DeserializeOwned + Debug,
+{
+    let params = DeleteParams {
+        preconditions: Some(Preconditions {
+            uid: object.uid(),
+            resource_version: object.resource_version(),
+        }),
+        ..DeleteParams::default()
+    };
+
+    match api.delete(&object.name(), &params).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(response)) if response.code == 404 => Ok(()),
+        Err(e) => Err(Error::KubeError(e)),
+    }
+}
@@ -1,5 +1,9 @@
 use super::OAuthConnection;
-use crate::Error;
+use crate::{
+    config::Config,
+    oauth_connection::{reload, storage},
+    Error,
+};
 
 use kube::{
     runtime::{controller::Action, events::Recorder},
@@ -7,12 +11,23 @@ use kube::{
 };
 
 use std::sync::Arc;
-use tokio::time::Duration;
 
+/// Keeps the token encrypted with the active key, for storage which encrypts it, and the reload
+/// targets annotated with its hash
 pub async fn connect(
-    _client: Client,
+    client: Client,
+    config: &Arc<Config>,
     _recorder: Recorder,
-    _oauth_connection: Arc<OAuthConnection>,
+    oauth_connection: Arc<OAuthConnection>,
 ) -> Result<Action, Error> {
-    Ok(Action::requeue(Duration::from_secs(60)))
+    let storage = storage::for_connection(&client, config, &oauth_connection);
+    storage.rotate(&oauth_connection).await?;
+
+    if !oauth_connection.spec.reload_targets.is_empty() {
+        if let Some(token) = storage.load(&oauth_connection).await? {
+            reload::reload(&client, &oauth_connection, &token).await?;
+        }
+    }
+
+    Ok(Action::requeue(config.requeue()))
 }
@@ -1,7 +1,8 @@
 use super::OAuthConnection;
 use crate::{
     api_version,
-    oauth_connection::{OAuthConnectionPhase, OAuthConnectionStatus},
+    config::Config,
+    oauth_connection::{Condition, OAuthConnectionPhase, OAuthConnectionStatus},
     Error,
 };
 use k8s_openapi::api::core::v1::Secret;
@@ -15,10 +16,10 @@ use kube::{
 };
 use serde_json::json;
 use std::sync::Arc;
-use tokio::time::Duration;
 
 pub async fn disconnected(
     client: Client,
+    config: &Config,
     recorder: Recorder,
     oauth_connection: Arc<OAuthConnection>,
 ) -> Result<Action, Error> {
@@ -36,7 +37,7 @@ pub async fn disconnected(
         ),
     };
 
-    let (_client_id, _client_secret) = match oauth_connection.load_client_keys(secrets).await {
+    let _credentials = match oauth_connection.load_client_credentials(secrets).await {
         Ok(secret) => secret,
         Err(_e) => {
             let new_status = Patch::Apply(json!({
@@ -44,12 +45,16 @@ pub async fn disconnected(
                 "kind": "OAuthConnection",
                 "status": OAuthConnectionStatus {
                     phase: Some(OAuthConnectionPhase::Initializing),
-                    expires_at: None,
-                    secret_name: None,
+                    conditions: vec![Condition::ready(
+                        false,
+                        "CredentialsUnavailable",
+                        "Client ID/Secret unavailable",
+                    )],
+                    ..OAuthConnectionStatus::default()
                 }
             }));
 
-            let patch_params = PatchParams::apply("chappaai").force();
+            let patch_params = PatchParams::apply(&config.field_manager).force();
             let _ = api
                 .patch_status(&name, &patch_params, &new_status)
                 .await
@@ -66,9 +71,9 @@ pub async fn disconnected(
                 .await
                 .map_err(Error::KubeError)?;
 
-            return Ok(Action::requeue(Duration::from_secs(60)));
+            return Ok(Action::requeue(config.requeue()));
         }
     };
 
-    Ok(Action::requeue(Duration::from_secs(60)))
+    Ok(Action::requeue(config.requeue()))
 }
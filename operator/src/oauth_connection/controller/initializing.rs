@@ -1,7 +1,8 @@
 use super::OAuthConnection;
 use crate::{
     api_version,
-    oauth_connection::{OAuthConnectionPhase, OAuthConnectionStatus},
+    config::Config,
+    oauth_connection::{Condition, OAuthConnectionPhase, OAuthConnectionStatus},
     Error,
 };
 use k8s_openapi::api::core::v1::Secret;
@@ -15,11 +16,11 @@ use kube::{
 };
 use serde_json::json;
 use std::sync::Arc;
-use tokio::time::Duration;
 use tracing::info;
 
 pub async fn initializing(
     client: Client,
+    config: &Config,
     recorder: Recorder,
     oauth_connection: Arc<OAuthConnection>,
 ) -> Result<Action, Error> {
@@ -37,7 +38,7 @@ pub async fn initializing(
         ),
     };
 
-    match oauth_connection.load_client_keys(secrets).await {
+    match oauth_connection.load_client_credentials(secrets).await {
         Ok(secret) => secret,
         Err(_e) => {
             recorder
@@ -50,7 +51,7 @@ pub async fn initializing(
                 })
                 .await
                 .map_err(Error::KubeError)?;
-            return Ok(Action::requeue(Duration::from_secs(60)));
+            return Ok(Action::requeue(config.requeue()));
         }
     };
 
@@ -59,12 +60,12 @@ pub async fn initializing(
         "kind": "OAuthConnection",
         "status": OAuthConnectionStatus {
             phase: Some(OAuthConnectionPhase::Disconnected),
-            expires_at: None,
-            secret_name: None,
+            conditions: vec![Condition::ready(false, "Disconnected", "Awaiting authorization")],
+            ..OAuthConnectionStatus::default()
         }
     }));
 
-    let patch_params = PatchParams::apply("chappaai").force();
+    let patch_params = PatchParams::apply(&config.field_manager).force();
     let _ = api
         .patch_status(&name, &patch_params, &new_status)
         .await
@@ -83,5 +84,5 @@ pub async fn initializing(
 
     info!("Reconciled OAuthConnection {}", name,);
 
-    Ok(Action::requeue(Duration::from_secs(60)))
+    Ok(Action::requeue(config.requeue()))
 }
@@ -1,20 +1,40 @@
-use super::{OAuthConnection, OAuthConnectionPhase, OAuthConnectionStatus};
-use crate::{kubernetes::controller, Error};
+use super::{Condition, OAuthConnection, OAuthConnectionPhase, OAuthConnectionStatus};
+use crate::{
+    config::Config,
+    kubernetes::{
+        controller,
+        leader::{self, Leadership},
+        reflector::{self, Synced},
+    },
+    metrics::Metrics,
+    shutdown::Shutdown,
+    Error,
+};
 use chrono::prelude::*;
 use futures::{future::BoxFuture, FutureExt, StreamExt};
 use kube::{
-    api::{Api, ListParams},
+    api::{Api, ListParams, Patch, PatchParams},
     client::Client,
     runtime::{
         controller::{Action, Context, Controller},
         events::Recorder,
         reflector::Store,
     },
-    Resource,
+    Resource, ResourceExt,
 };
+use serde_json::json;
 use std::sync::Arc;
-use tokio::{sync::RwLock, time::Duration};
-use tracing::warn;
+use tokio::sync::RwLock;
+use tracing::{instrument, warn};
+
+const CONTROLLER_NAME: &str = "oauth-connections";
+
+mod access;
+use access::access;
+mod secret_targets;
+use secret_targets::secret_targets;
+mod token_storage;
+use token_storage::token_storage;
 
 // Controller States
 mod none;
@@ -33,18 +53,27 @@ pub struct Manager {
 
     /// In memory state
     state: Arc<RwLock<controller::State>>,
+
+    /// Whether the store served to the HTTP API has synced
+    synced: Synced,
 }
 
 impl Manager {
-    pub async fn new(client: Client) -> (Self, Store<OAuthConnection>, BoxFuture<'static, ()>) {
-        let state = Arc::new(RwLock::new(controller::State::new(String::from(
-            "oauth-connections",
-        ))));
-
-        let context = Context::new(controller::Data {
-            client: client.clone(),
-            state: state.clone(),
-        });
+    pub async fn new(
+        client: Client,
+        config: Arc<Config>,
+        metrics: Arc<Metrics>,
+        leadership: Leadership,
+        shutdown: Shutdown,
+    ) -> (Self, Store<OAuthConnection>, BoxFuture<'static, ()>) {
+        let state = Arc::new(RwLock::new(controller::State::new(String::from(CONTROLLER_NAME))));
+
+        let context = Context::new(controller::Data::new(
+            client.clone(),
+            state.clone(),
+            metrics,
+            config,
+        ));
 
         let api_services = Api::<OAuthConnection>::default_namespaced(client.clone());
 
@@ -54,18 +83,36 @@ impl Manager {
             .await
             .expect("Unable to access OAuthConnection's within the current namespace");
 
-        // All good. Start controller and return its future.
-        let drainer = Controller::new(api_services, ListParams::default());
-
-        let store = drainer.store();
-
-        let drainer = drainer
-            .run(reconcile, error_policy, context)
-            .filter_map(|x| async move { std::result::Result::ok(x) })
-            .for_each(|_| futures::future::ready(()))
+        // The HTTP API reads from its own reflector on every replica, while the controller
+        // only runs on the leader.
+        let (store, synced, reflector) = reflector::reflect(api_services.clone());
+
+        let drainer = leader::while_leading(leadership, CONTROLLER_NAME, shutdown.clone(), move || {
+            Controller::new(api_services.clone(), ListParams::default())
+                .graceful_shutdown_on(shutdown.clone())
+                .run(reconcile, error_policy, context.clone())
+                .for_each({
+                    let context = context.clone();
+                    move |result| {
+                        context.get_ref().forget_deleted(&result);
+                        futures::future::ready(())
+                    }
+                })
+                .boxed()
+        });
+        let drainer = futures::future::select(drainer.boxed(), reflector)
+            .map(|_| ())
             .boxed();
 
-        (Self { client, state }, store, drainer)
+        (
+            Self {
+                client,
+                state,
+                synced,
+            },
+            store,
+            drainer,
+        )
     }
 
     /// Client getter
@@ -77,13 +124,66 @@ impl Manager {
     pub async fn state(&self) -> controller::State {
         self.state.read().await.clone()
     }
+
+    /// Whether the reflector store has completed its initial list
+    pub fn synced(&self) -> bool {
+        self.synced.get()
+    }
 }
 
-fn error_policy(error: &Error, _: Context<controller::Data>) -> Action {
+/// Adds or removes one of the connection's finalizers. The finalizers are read afresh, as another
+/// may have changed since the connection was cached, and replaced only if unchanged since.
+async fn set_finalizer(
+    client: &Client,
+    oauth_connection: &OAuthConnection,
+    finalizer: &str,
+    present: bool,
+) -> Result<(), Error> {
+    let api: Api<OAuthConnection> = match oauth_connection.namespace() {
+        Some(namespace) => Api::namespaced(client.clone(), &namespace),
+        None => Api::default_namespaced(client.clone()),
+    };
+
+    let live = match api.get_opt(&oauth_connection.name()).await? {
+        Some(live) => live,
+        None => return Ok(()),
+    };
+
+    if live.finalizers().iter().any(|existing| existing == finalizer) == present {
+        return Ok(());
+    }
+
+    let mut finalizers: Vec<String> = live
+        .finalizers()
+        .iter()
+        .filter(|existing| *existing != finalizer)
+        .cloned()
+        .collect();
+    if present {
+        finalizers.push(finalizer.to_string());
+    }
+
+    let patch = Patch::Merge(json!({
+        "metadata": {
+            "resourceVersion": live.resource_version(),
+            "finalizers": finalizers,
+        }
+    }));
+
+    api.patch(&oauth_connection.name(), &PatchParams::default(), &patch)
+        .await?;
+
+    Ok(())
+}
+
+/// Failures are requeued by `reconcile`, which knows the object that failed, so this only
+/// guards against errors escaping it.
+fn error_policy(error: &Error, ctx: Context<controller::Data>) -> Action {
     warn!("reconcile failed: {:?}", error);
-    Action::requeue(Duration::from_secs(5 * 60))
+    Action::requeue(ctx.get_ref().config.error_backoff_max())
 }
 
+#[instrument(skip_all, fields(connection = %oauth_connection.name(), api = %oauth_connection.spec.api))]
 async fn reconcile(
     oauth_connection: Arc<OAuthConnection>,
     ctx: Context<controller::Data>,
@@ -94,15 +194,42 @@ async fn reconcile(
     let reporter = ctx.get_ref().state.read().await.reporter.clone();
     let recorder = Recorder::new(client.clone(), reporter.clone(), oauth_connection.object_ref(&()));
 
-    match &oauth_connection.status {
-        Some(status) => match &status.phase {
-            Some(phase) => match &phase {
-                OAuthConnectionPhase::Initializing => initializing(client, recorder, oauth_connection).await,
-                OAuthConnectionPhase::Disconnected => disconnected(client, recorder, oauth_connection).await,
-                OAuthConnectionPhase::Connected => connect(client, recorder, oauth_connection).await,
+    let config = ctx.get_ref().config.clone();
+    let object = oauth_connection.clone();
+
+    let reconciliation = async {
+        // Copies of the token, and tokens kept in files, are all that's left to clean up, the rest
+        // being owned by the connection
+        if oauth_connection.metadata.deletion_timestamp.is_some() {
+            secret_targets(client.clone(), &config, &oauth_connection).await?;
+            token_storage(client, &config, &oauth_connection).await?;
+            return Ok(Action::await_change());
+        }
+
+        access(client.clone(), &config, &oauth_connection).await?;
+        secret_targets(client.clone(), &config, &oauth_connection).await?;
+        token_storage(client.clone(), &config, &oauth_connection).await?;
+
+        match &oauth_connection.status {
+            Some(status) => match &status.phase {
+                Some(phase) => match &phase {
+                    OAuthConnectionPhase::Initializing => {
+                        initializing(client, &config, recorder, oauth_connection).await
+                    }
+                    OAuthConnectionPhase::Disconnected => {
+                        disconnected(client, &config, recorder, oauth_connection).await
+                    }
+                    OAuthConnectionPhase::Connected => {
+                        connect(client, &config, recorder, oauth_connection).await
+                    }
+                },
+                None => none(client, &config, recorder, oauth_connection).await,
             },
-            None => none(client, recorder, oauth_connection).await,
-        },
-        None => none(client, recorder, oauth_connection).await,
-    }
+            None => none(client, &config, recorder, oauth_connection).await,
+        }
+    };
+
+    ctx.get_ref()
+        .reconcile(CONTROLLER_NAME, object.as_ref(), reconciliation)
+        .await
 }
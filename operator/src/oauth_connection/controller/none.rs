@@ -1,5 +1,5 @@
-use super::{OAuthConnection, OAuthConnectionPhase, OAuthConnectionStatus};
-use crate::{api_version, Error};
+use super::{Condition, OAuthConnection, OAuthConnectionPhase, OAuthConnectionStatus};
+use crate::{api_version, config::Config, Error};
 use kube::{
     api::{Api, Patch, PatchParams},
     runtime::{
@@ -14,6 +14,7 @@ use tracing::info;
 
 pub async fn none(
     client: Client,
+    config: &Config,
     recorder: Recorder,
     oauth_connection: Arc<OAuthConnection>,
 ) -> Result<Action, Error> {
@@ -30,12 +31,12 @@ pub async fn none(
         "kind": "OAuthConnection",
         "status": OAuthConnectionStatus {
             phase: Some(OAuthConnectionPhase::Initializing),
-                        expires_at: None,
-                        secret_name: None,
+            conditions: vec![Condition::ready(false, "Initializing", "Waiting for client credentials")],
+            ..OAuthConnectionStatus::default()
         }
     }));
 
-    let patch_params = PatchParams::apply("chappaai").force();
+    let patch_params = PatchParams::apply(&config.field_manager).force();
     let _ = api
         .patch_status(&name, &patch_params, &new_status)
         .await
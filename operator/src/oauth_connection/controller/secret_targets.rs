@@ -0,0 +1,64 @@
+use super::{set_finalizer, OAuthConnection, OAuthConnectionPhase};
+use crate::{
+    config::Config,
+    oauth_connection::{storage, targets},
+    Error,
+};
+use kube::{Client, ResourceExt};
+use std::sync::Arc;
+
+/// Keeps the copies of a connected connection's token in its target namespaces, removing those no
+/// longer targeted. The connection holds a finalizer while it has targets, so that its copies are
+/// removed before it is deleted.
+pub async fn secret_targets(
+    client: Client,
+    config: &Arc<Config>,
+    oauth_connection: &OAuthConnection,
+) -> Result<(), Error> {
+    let has_finalizer = oauth_connection
+        .finalizers()
+        .iter()
+        .any(|finalizer| finalizer == targets::FINALIZER);
+
+    if oauth_connection.metadata.deletion_timestamp.is_some()
+        || oauth_connection.spec.secret_targets.is_empty()
+    {
+        if has_finalizer {
+            targets::prune(&client, config, oauth_connection, &[]).await?;
+            set_finalizer(&client, oauth_connection, targets::FINALIZER, false).await?;
+        }
+
+        return Ok(());
+    }
+
+    if !has_finalizer {
+        set_finalizer(&client, oauth_connection, targets::FINALIZER, true).await?;
+    }
+
+    targets::prune(
+        &client,
+        config,
+        oauth_connection,
+        &oauth_connection.spec.secret_targets,
+    )
+    .await?;
+
+    let connected = matches!(
+        oauth_connection
+            .status
+            .as_ref()
+            .and_then(|status| status.phase.as_ref()),
+        Some(OAuthConnectionPhase::Connected)
+    );
+
+    if connected {
+        if let Some(token) = storage::for_connection(&client, config, oauth_connection)
+            .load(oauth_connection)
+            .await?
+        {
+            targets::replicate(&client, config, oauth_connection, &token).await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use super::{set_finalizer, OAuthConnection};
+use crate::{
+    config::Config,
+    oauth_connection::{storage, TokenStorageBackend},
+    Error,
+};
+use kube::{Client, ResourceExt};
+use std::sync::Arc;
+
+/// Held by connections whose token storage isn't owned by them, and so isn't garbage collected
+const FINALIZER: &str = "chappaai.dev/token-storage";
+
+/// Removes the token of a deleted connection from storage the API server doesn't clean up, which
+/// is the token directory. The connection holds a finalizer until it has.
+pub async fn token_storage(
+    client: Client,
+    config: &Arc<Config>,
+    oauth_connection: &OAuthConnection,
+) -> Result<(), Error> {
+    let has_finalizer = oauth_connection
+        .finalizers()
+        .iter()
+        .any(|finalizer| finalizer == FINALIZER);
+
+    if oauth_connection.metadata.deletion_timestamp.is_some() {
+        if has_finalizer {
+            storage::for_connection(&client, config, oauth_connection)
+                .delete(oauth_connection)
+                .await?;
+            set_finalizer(&client, oauth_connection, FINALIZER, false).await?;
+        }
+
+        return Ok(());
+    }
+
+    if oauth_connection.spec.storage == TokenStorageBackend::File && !has_finalizer {
+        set_finalizer(&client, oauth_connection, FINALIZER, true).await?;
+    }
+
+    Ok(())
+}
@@ -1,7 +1,28 @@
 pub mod api;
 
+mod authorizations;
+pub use authorizations::PendingAuthorizations;
+
+mod client_assertion;
+
 mod controller;
 pub use controller::Manager;
 
+pub mod proxy;
+
+mod reload;
+mod storage;
+mod targets;
+pub(crate) use targets::check_target_namespaces;
+
+mod token;
+mod token_client;
+pub use token::TokenRefreshes;
+
 mod resource;
-pub use resource::{OAuthConnection, OAuthConnectionPhase, OAuthConnectionSpec, OAuthConnectionStatus};
+pub use resource::{
+    AccessPolicy, Condition, OAuthConnection, OAuthConnectionPhase, OAuthConnectionSpec,
+    OAuthConnectionStatus, ReloadTarget, SecretTarget, ServiceAccountRef, TokenStorageBackend, WorkloadKind,
+};
+
+pub mod v2;
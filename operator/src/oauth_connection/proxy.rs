@@ -0,0 +1,154 @@
+use super::{
+    api::oauth_connection_and_api,
+    token::{self, StoredToken},
+};
+use crate::{authentication, ApplicationState, Error, OAuthApi, Result};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::Path,
+    http::{
+        header::{self, HeaderMap, HeaderName, HeaderValue},
+        Method, Request, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Extension,
+};
+use http_body::{LengthLimitError, Limited};
+use kube::ResourceExt;
+use std::sync::Arc;
+use tracing::{info_span, instrument, Instrument};
+
+/// Headers which only describe a single hop, or this operator, and so aren't forwarded either way
+const UNFORWARDED_HEADERS: [HeaderName; 11] = [
+    header::AUTHORIZATION,
+    header::CONNECTION,
+    header::CONTENT_LENGTH,
+    header::COOKIE,
+    header::HOST,
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+/// Forwards a request to the connection's API, authenticated with its token, so that workloads
+/// never handle the token themselves. Callers are authenticated and authorized as for the token
+/// API. A token which has expired, or which the API rejects with a `401`, is refreshed and the
+/// request retried once.
+#[instrument(skip_all, fields(connection = %name, caller))]
+pub async fn proxy(
+    Path((name, path)): Path<(String, String)>,
+    Extension(state): Extension<Arc<ApplicationState>>,
+    request: Request<Body>,
+) -> Result<Response> {
+    let caller = authentication::authenticate(&state, request.headers()).await?;
+    tracing::Span::current().record("caller", &caller.username.as_str());
+
+    let (oac, oaa) = oauth_connection_and_api(&state, &name)?;
+    authentication::authorize(&state, &caller, &oac, "get").await?;
+
+    let (parts, body) = request.into_parts();
+    let limit = state.config.proxy_body_limit_bytes;
+    let body = hyper::body::to_bytes(Limited::new(body, limit))
+        .await
+        .map_err(|e| match e.downcast_ref::<LengthLimitError>() {
+            Some(_) => Error::PayloadTooLarge(limit),
+            None => Error::InvalidRequest(format!("Failed to read body: {}", e)),
+        })?;
+
+    let url = match parts.uri.query() {
+        Some(query) => format!("{}?{}", upstream_url(&oaa, &path), query),
+        None => upstream_url(&oaa, &path),
+    };
+
+    let mut token = token::current(&state, &oac, &oaa).await?;
+
+    let mut response = send(
+        &state.http,
+        &oaa,
+        &parts.method,
+        &parts.headers,
+        &url,
+        &body,
+        &token,
+    )
+    .await?;
+
+    if response.status() == StatusCode::UNAUTHORIZED && token.refresh_token.is_some() {
+        token = token::refresh(&state, &oac, &oaa, &token).await?;
+        response = send(
+            &state.http,
+            &oaa,
+            &parts.method,
+            &parts.headers,
+            &url,
+            &body,
+            &token,
+        )
+        .await?;
+    }
+
+    let status = response.status();
+    let headers = forwarded(response.headers());
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| Error::UpstreamError(e.to_string()))?;
+
+    Ok((status, headers, body).into_response())
+}
+
+fn upstream_url(oaa: &OAuthApi, path: &str) -> String {
+    format!(
+        "{}/{}",
+        oaa.spec.http.base_url.trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
+
+fn forwarded(headers: &HeaderMap) -> HeaderMap {
+    let mut headers = headers.clone();
+    for name in UNFORWARDED_HEADERS.iter() {
+        headers.remove(name);
+    }
+
+    headers
+}
+
+async fn send(
+    http: &reqwest::Client,
+    oaa: &OAuthApi,
+    method: &Method,
+    headers: &HeaderMap,
+    url: &str,
+    body: &Bytes,
+    token: &StoredToken,
+) -> Result<reqwest::Response> {
+    let mut headers = forwarded(headers);
+
+    // The API's own headers take precedence over the caller's
+    for default in &oaa.spec.http.headers {
+        let name = HeaderName::from_bytes(default.key.as_bytes())
+            .map_err(|e| Error::InvalidSpec(format!("Invalid header {}: {}", default.key, e)))?;
+        let value = HeaderValue::from_str(&default.value)
+            .map_err(|e| Error::InvalidSpec(format!("Invalid value for header {}: {}", default.key, e)))?;
+        headers.insert(name, value);
+    }
+
+    let mut authorization =
+        HeaderValue::from_str(&oaa.spec.http.authorization_header(&token.access_token))
+            .map_err(|_| Error::UpstreamError(String::from("Token is not a valid header value")))?;
+    authorization.set_sensitive(true);
+    headers.insert(header::AUTHORIZATION, authorization);
+
+    http.request(method.clone(), url)
+        .headers(headers)
+        .body(body.clone())
+        .send()
+        .instrument(info_span!("upstream_request", api = %oaa.name(), method = %method))
+        .await
+        .map_err(|e| Error::UpstreamError(e.to_string()))
+}
@@ -0,0 +1,110 @@
+//! Rollouts of the workloads listed by a connection's `reloadTargets` whenever its token changes,
+//! for workloads which only read the token at startup. Each is annotated with a hash of the token
+//! in its pod template, so that a new token rolls out new pods, and an unchanged one doesn't.
+
+use super::{targets::check_namespace, token::StoredToken, OAuthConnection, ReloadTarget, WorkloadKind};
+use crate::{Error, Result};
+use k8s_openapi::api::apps::v1::{DaemonSet, Deployment, StatefulSet};
+use kube::{
+    api::{Api, ListParams, Patch, PatchParams},
+    Client, Resource, ResourceExt,
+};
+use ring::digest::{digest, SHA256};
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use std::fmt::Debug;
+use tracing::warn;
+
+const TOKEN_HASH_ANNOTATION: &str = "chappaai.dev/token-hash";
+
+/// A short hash of the access token, which identifies it without revealing it
+fn token_hash(token: &StoredToken) -> String {
+    digest(&SHA256, token.access_token.as_bytes())
+        .as_ref()
+        .iter()
+        .take(8)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Annotates the pod templates of every reload target with the token's hash
+pub(crate) async fn reload(
+    client: &Client,
+    oauth_connection: &OAuthConnection,
+    token: &StoredToken,
+) -> Result<()> {
+    let hash = token_hash(token);
+
+    for target in &oauth_connection.spec.reload_targets {
+        let namespace = target.namespace.clone().or_else(|| oauth_connection.namespace());
+        if let Some(namespace) = &namespace {
+            check_namespace(client, oauth_connection, namespace).await?;
+        }
+
+        match target.kind {
+            WorkloadKind::Deployment => annotate::<Deployment>(client, &namespace, target, &hash).await?,
+            WorkloadKind::StatefulSet => annotate::<StatefulSet>(client, &namespace, target, &hash).await?,
+            WorkloadKind::DaemonSet => annotate::<DaemonSet>(client, &namespace, target, &hash).await?,
+        }
+    }
+
+    Ok(())
+}
+
+async fn annotate<K>(
+    client: &Client,
+    namespace: &Option<String>,
+    target: &ReloadTarget,
+    hash: &str,
+) -> Result<()>
+where
+    K: Resource<DynamicType = ()> + Clone + DeserializeOwned + Debug,
+{
+    let api: Api<K> = match namespace {
+        Some(namespace) => Api::namespaced(client.clone(), namespace),
+        None => Api::default_namespaced(client.clone()),
+    };
+
+    let names = match (&target.name, &target.selector) {
+        (Some(name), _) => vec![name.clone()],
+        (None, Some(selector)) => {
+            let selector = selector
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>()
+                .join(",");
+
+            api.list(&ListParams::default().labels(&selector))
+                .await?
+                .into_iter()
+                .map(|workload| workload.name())
+                .collect()
+        }
+        (None, None) => vec![],
+    };
+
+    let patch = Patch::Merge(json!({
+        "spec": {
+            "template": {
+                "metadata": {
+                    "annotations": {
+                        TOKEN_HASH_ANNOTATION: hash,
+                    }
+                }
+            }
+        }
+    }));
+
+    // Workloads which don't exist yet will read the current token when they start
+    for name in names {
+        match api.patch(&name, &PatchParams::default(), &patch).await {
+            Ok(_) => {}
+            Err(kube::Error::Api(response)) if response.code == 404 => {
+                warn!("Reload target {} {} not found", K::kind(&()), name)
+            }
+            Err(e) => return Err(Error::KubeError(e)),
+        }
+    }
+
+    Ok(())
+}
@@ -1,8 +1,16 @@
-use crate::{kubernetes::get_string_value, Error};
+use crate::{
+    authentication::Caller,
+    config::Config,
+    kubernetes::{get_string_value, schema},
+    oauth_api::{unreserved_params, validate_unreserved_params, AuthorizationParams, OAuthApi},
+    Error,
+};
+use chrono::Utc;
 use k8s_openapi::api::core::v1::Secret;
-use kube::{Api, CustomResource};
-use schemars::JsonSchema;
+use kube::{Api, CustomResource, ResourceExt};
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
 #[kube(
@@ -10,39 +18,334 @@ use serde::{Deserialize, Serialize};
     version = "v1",
     kind = "OAuthConnection",
     status = "OAuthConnectionStatus",
+    shortname = "oconn",
+    category = "chappaai",
+    printcolumn = r#"{"name":"API", "type":"string", "description":"OAuthApi connected to", "jsonPath":".spec.api"}"#,
     printcolumn = r#"{"name":"Status", "type":"string", "description":"current connection status", "jsonPath":".status.phase"}"#,
-    printcolumn = r#"{"name":"Expiry", "type":"string", "description":"token expiry", "jsonPath":".status.expires_at"}"#,
+    printcolumn = r#"{"name":"Identity", "type":"string", "description":"account which authorized the connection", "jsonPath":".status.identity"}"#,
+    printcolumn = r#"{"name":"Expiry", "type":"string", "description":"token expiry", "jsonPath":".status.expiresAt"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#,
     namespaced
 )]
 #[serde(rename_all = "camelCase")]
 pub struct OAuthConnectionSpec {
+    /// Name of the OAuthApi, in the same namespace, to connect to
+    #[schemars(schema_with = "schema::immutable_string")]
     pub api: String,
+
+    /// Requested alongside the OAuthApi's required scopes, or replaced by its default scopes when
+    /// empty
+    #[serde(default)]
+    #[schemars(schema_with = "non_blank_scopes")]
     pub scopes: Vec<String>,
     pub credentials: CredentialOptions,
+
+    /// Sent with the authorization request, replacing the OAuthApi's parameters of the same name
+    #[serde(default)]
+    #[schemars(schema_with = "unreserved_params")]
+    pub authorization_params: Vec<AuthorizationParams>,
+
+    /// Sent with token requests, replacing the OAuthApi's parameters of the same name
+    #[serde(default)]
+    #[schemars(schema_with = "unreserved_params")]
+    pub extra_token_params: Vec<AuthorizationParams>,
+
+    /// Restricts which ServiceAccounts may use the connection through the HTTP API. Any caller
+    /// RBAC allows may use it when this is unset.
+    pub access: Option<AccessPolicy>,
+
+    /// Namespaces to keep copies of the token Secret in, for workloads outside the connection's
+    /// namespace to mount
+    #[serde(default)]
+    pub secret_targets: Vec<SecretTarget>,
+
+    /// Workloads rolled out whenever the token changes, for those which only read it at startup
+    #[serde(default)]
+    #[schemars(schema_with = "reload_targets")]
+    pub reload_targets: Vec<ReloadTarget>,
+
+    /// Where the token is kept, which can't be changed
+    #[serde(default)]
+    #[schemars(schema_with = "immutable_storage")]
+    pub storage: TokenStorageBackend,
+}
+
+/// A workload, or the workloads with all of the `selector`'s labels
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReloadTarget {
+    pub kind: WorkloadKind,
+    pub name: Option<String>,
+    pub selector: Option<BTreeMap<String, String>>,
+    /// Defaults to the OAuthConnection's namespace
+    pub namespace: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+pub enum WorkloadKind {
+    Deployment,
+    StatefulSet,
+    DaemonSet,
+}
+
+/// Reload targets naming either a workload or a selector, bounded to keep the cost of the rule
+/// within the API server's budget
+fn reload_targets(gen: &mut SchemaGenerator) -> Schema {
+    let target = schema::with_rules::<ReloadTarget>(gen, &[(
+        "has(self.name) != has(self.selector)",
+        "exactly one of name and selector is required",
+    )]);
+
+    let mut schema = gen.subschema_for::<Vec<ReloadTarget>>().into_object();
+    schema.array().max_items = Some(32);
+    schema.array().items = Some(target.into());
+
+    Schema::Object(schema)
+}
+
+/// `Secret` holds the token in a Secret, `EncryptedSecret` encrypts it with the operator's key
+/// before doing so, and `File` writes it beneath the operator's token directory instead
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub enum TokenStorageBackend {
+    #[default]
+    Secret,
+    EncryptedSecret,
+    File,
+}
+
+fn immutable_storage(gen: &mut SchemaGenerator) -> Schema {
+    schema::with_rules::<TokenStorageBackend>(gen, &[("self == oldSelf", "is immutable")])
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SecretTarget {
+    #[schemars(length(min = 1))]
+    pub namespace: String,
+    /// Defaults to the name of the token Secret
+    pub name: Option<String>,
+}
+
+/// ServiceAccounts permitted to use a connection, which are also granted `get` on its token
+/// Secret and `token` subresource
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessPolicy {
+    #[serde(default)]
+    pub service_accounts: Vec<ServiceAccountRef>,
+    /// Namespaces in which every ServiceAccount is permitted
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceAccountRef {
+    #[schemars(length(min = 1))]
+    pub name: String,
+    /// Defaults to the OAuthConnection's namespace
+    pub namespace: Option<String>,
+}
+
+/// Scopes, none of which are blank
+fn non_blank_scopes(gen: &mut SchemaGenerator) -> Schema {
+    let mut scope = gen.subschema_for::<String>().into_object();
+    scope.string().pattern = Some(String::from(r"\S"));
+
+    let mut schema = gen.subschema_for::<Vec<String>>().into_object();
+    schema.array().items = Some(Schema::Object(scope).into());
+
+    Schema::Object(schema)
+}
+
+/// The parameters of `defaults`, replaced by those of `overrides` of the same name
+fn merge_params(
+    defaults: &[AuthorizationParams],
+    overrides: &[AuthorizationParams],
+) -> Vec<(String, String)> {
+    defaults
+        .iter()
+        .filter(|param| !overrides.iter().any(|other| other.key == param.key))
+        .chain(overrides)
+        .map(|param| (param.key.clone(), param.value.clone()))
+        .collect()
 }
 
 impl OAuthConnection {
-    pub async fn load_client_keys(&self, secrets: Api<Secret>) -> Result<(String, String), Error> {
+    /// Checks what the CRD schema can't: that scopes aren't blank, parameters aren't those the
+    /// operator sets, access namespaces aren't blank, copies of the token don't replace it, reload
+    /// targets select workloads and client credentials are read from the connection's own namespace
+    pub fn validate(&self, config: &Config) -> Result<(), Error> {
+        if self.spec.scopes.iter().any(|scope| scope.trim().is_empty()) {
+            return Err(Error::InvalidSpec(String::from("scopes must not be blank")));
+        }
+
+        validate_unreserved_params("authorizationParams", &self.spec.authorization_params)?;
+        validate_unreserved_params("extraTokenParams", &self.spec.extra_token_params)?;
+
+        self.validate_secret_targets(config)?;
+
+        if let Some(access) = &self.spec.access {
+            if access
+                .namespaces
+                .iter()
+                .any(|namespace| namespace.trim().is_empty())
+            {
+                return Err(Error::InvalidSpec(String::from(
+                    "access.namespaces must not be blank",
+                )));
+            }
+        }
+
+        if self.spec.reload_targets.iter().any(|target| {
+            target.name.is_some() == target.selector.is_some()
+                || target
+                    .selector
+                    .as_ref()
+                    .is_some_and(|selector| selector.is_empty())
+        }) {
+            return Err(Error::InvalidSpec(String::from(
+                "reloadTargets must each have either a name or a non-empty selector",
+            )));
+        }
+
+        match &self.spec.credentials {
+            CredentialOptions::SecretRef(SecretRef {
+                namespace: Some(namespace),
+                ..
+            }) if Some(namespace) != self.metadata.namespace.as_ref() => Err(Error::InvalidSpec(format!(
+                "credentials.secretRef.namespace {} must be the OAuthConnection's own namespace",
+                namespace
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Copies of the token are plaintext Secrets, which would defeat storage that keeps the token
+    /// encrypted or away from the API server. Nor may a copy be the token Secret itself, which it
+    /// would overwrite, and pruning then delete.
+    pub fn validate_secret_targets(&self, config: &Config) -> Result<(), Error> {
+        if !self.spec.secret_targets.is_empty() && self.spec.storage != TokenStorageBackend::Secret {
+            return Err(Error::InvalidSpec(format!(
+                "secretTargets requires storage Secret, not {:?}",
+                self.spec.storage
+            )));
+        }
+
+        let secret_name = config.secret_name(&self.name());
+        if self.spec.secret_targets.iter().any(|target| {
+            Some(&target.namespace) == self.metadata.namespace.as_ref()
+                && target.name.as_ref().is_none_or(|name| *name == secret_name)
+        }) {
+            return Err(Error::InvalidSpec(format!(
+                "secretTargets in the OAuthConnection's own namespace must name a Secret other than {}",
+                secret_name
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the access policy permits the caller, who must be one of the listed ServiceAccounts
+    /// or in one of the listed namespaces
+    pub fn permits(&self, caller: &Caller) -> bool {
+        let access = match &self.spec.access {
+            Some(access) => access,
+            None => return true,
+        };
+
+        let (namespace, name) = match caller.service_account() {
+            Some(service_account) => service_account,
+            None => return false,
+        };
+
+        access.namespaces.iter().any(|permitted| permitted == namespace)
+            || access.service_accounts.iter().any(|permitted| {
+                permitted.name == name
+                    && permitted
+                        .namespace
+                        .as_deref()
+                        .or(self.metadata.namespace.as_deref())
+                        == Some(namespace)
+            })
+    }
+
+    /// The connection's scopes, or the API's default scopes if it has none, with the API's required
+    /// scopes, each requested once
+    pub fn requested_scopes(&self, oauth_api: &OAuthApi) -> Vec<String> {
+        let spec = oauth_api.oauth2();
+        let default_scopes = spec
+            .map(|spec| spec.default_scopes.as_slice())
+            .unwrap_or_default();
+        let required_scopes = spec
+            .map(|spec| spec.required_scopes.as_slice())
+            .unwrap_or_default();
+
+        let scopes = match self.spec.scopes.is_empty() {
+            true => default_scopes,
+            false => self.spec.scopes.as_slice(),
+        };
+
+        let mut requested: Vec<String> = vec![];
+        for scope in scopes.iter().chain(required_scopes) {
+            if !requested.contains(scope) {
+                requested.push(scope.clone());
+            }
+        }
+
+        requested
+    }
+
+    /// Parameters of the authorization request: the API's, and the connection's in their place
+    pub fn authorization_params(&self, oauth_api: &OAuthApi) -> Vec<(String, String)> {
+        let defaults = oauth_api
+            .oauth2()
+            .map(|spec| spec.authorization_params.as_slice())
+            .unwrap_or_default();
+
+        merge_params(defaults, &self.spec.authorization_params)
+    }
+
+    /// Extra parameters of token requests: the API's, and the connection's in their place
+    pub fn extra_token_params(&self, oauth_api: &OAuthApi) -> Vec<(String, String)> {
+        let defaults = oauth_api
+            .oauth2()
+            .map(|spec| spec.extra_token_params.as_slice())
+            .unwrap_or_default();
+
+        merge_params(defaults, &self.spec.extra_token_params)
+    }
+
+    /// Reads the client credentials from their Secret. Only the client ID is required here; which
+    /// of the secret and private key are required depends on the API's `tokenEndpointAuthMethod`.
+    pub async fn load_client_credentials(&self, secrets: Api<Secret>) -> Result<ClientCredentials, Error> {
         match &self.spec.credentials {
             CredentialOptions::SecretRef(secret_ref) => {
                 let secret = secrets.get(&secret_ref.name).await.map_err(Error::KubeError)?;
 
-                let client_id = match get_string_value(&secret, &secret_ref.id_key) {
-                    Ok(value) => value,
-                    Err(error) => return Err(error),
-                };
-
-                let client_secret = match get_string_value(&secret, &secret_ref.secret_key) {
-                    Ok(value) => value,
-                    Err(error) => return Err(error),
+                let optional = |key: &str| match &secret.data {
+                    Some(data) if data.contains_key(key) => get_string_value(&secret, key).map(Some),
+                    _ => Ok(None),
                 };
 
-                Ok((client_id, client_secret))
+                Ok(ClientCredentials {
+                    client_id: get_string_value(&secret, &secret_ref.id_key)?,
+                    client_secret: optional(&secret_ref.secret_key)?,
+                    private_key: optional(&secret_ref.private_key_key)?,
+                })
             }
         }
     }
 }
 
+/// A connection's client credentials
+pub struct ClientCredentials {
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    /// PEM encoded, for `private_key_jwt`
+    pub private_key: Option<String>,
+}
+
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub enum CredentialOptions {
@@ -53,9 +356,28 @@ pub enum CredentialOptions {
 #[serde(rename_all = "camelCase")]
 pub struct SecretRef {
     namespace: Option<String>,
+    #[schemars(length(min = 1))]
     name: String,
+    #[serde(default = "default_id_key")]
     id_key: String,
+    #[serde(default = "default_secret_key")]
     secret_key: String,
+    /// Key of the PEM encoded private key which signs client assertions, for APIs authenticating
+    /// clients with `private_key_jwt`
+    #[serde(default = "default_private_key_key")]
+    private_key_key: String,
+}
+
+fn default_id_key() -> String {
+    String::from("clientId")
+}
+
+fn default_secret_key() -> String {
+    String::from("clientSecret")
+}
+
+fn default_private_key_key() -> String {
+    String::from("privateKey")
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
@@ -66,10 +388,42 @@ pub enum OAuthConnectionPhase {
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct OAuthConnectionStatus {
     pub phase: Option<OAuthConnectionPhase>,
     pub secret_name: Option<String>,
     pub expires_at: Option<String>,
+    /// Scopes granted by the provider, which may differ from those requested
+    pub granted_scopes: Option<Vec<String>>,
+    /// Identity of the account that authorized the connection
+    pub identity: Option<String>,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Condition {
+    #[serde(rename = "type")]
+    pub type_: String,
+    #[schemars(regex(pattern = "^(True|False|Unknown)$"))]
+    pub status: String,
+    pub reason: Option<String>,
+    pub message: Option<String>,
+    pub last_transition_time: Option<String>,
+}
+
+impl Condition {
+    /// The `Ready` condition, true once the connection holds a usable token
+    pub fn ready(ready: bool, reason: &str, message: &str) -> Self {
+        Condition {
+            type_: String::from("Ready"),
+            status: String::from(if ready { "True" } else { "False" }),
+            reason: Some(reason.to_string()),
+            message: Some(message.to_string()),
+            last_transition_time: Some(Utc::now().to_rfc3339()),
+        }
+    }
 }
 
 impl From<&OAuthConnectionPhase> for String {
@@ -81,3 +435,137 @@ impl From<&OAuthConnectionPhase> for String {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn oauth_api() -> OAuthApi {
+        OAuthApi::new(
+            "google",
+            serde_json::from_value(json!({
+                "http": { "baseUrl": "https://www.googleapis.com/" },
+                "auth": {
+                    "oAuth2": {
+                        "authorizationUrl": "https://accounts.google.com/o/oauth2/v2/auth",
+                        "authorizationParams": [
+                            { "key": "access_type", "value": "offline" },
+                            { "key": "prompt", "value": "consent" },
+                        ],
+                        "defaultScopes": ["email", "profile"],
+                        "requiredScopes": ["openid", "email"],
+                        "tokenUrl": "https://oauth2.googleapis.com/token",
+                        "extraTokenParams": [{ "key": "audience", "value": "default" }],
+                    },
+                },
+            }))
+            .unwrap(),
+        )
+    }
+
+    fn oauth_connection(spec: serde_json::Value) -> OAuthConnection {
+        let mut spec = spec;
+        spec["api"] = json!("google");
+        spec["credentials"] = json!({ "secretRef": { "name": "google" } });
+
+        OAuthConnection::new("google", serde_json::from_value(spec).unwrap())
+    }
+
+    fn params(params: &[(&str, &str)]) -> Vec<(String, String)> {
+        params
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn requests_default_scopes_without_scopes_of_its_own() {
+        let oac = oauth_connection(json!({}));
+
+        assert_eq!(oac.requested_scopes(&oauth_api()), ["email", "profile", "openid"]);
+    }
+
+    #[test]
+    fn requests_its_scopes_and_required_scopes_once_each() {
+        let oac = oauth_connection(json!({ "scopes": ["calendar", "email", "calendar"] }));
+
+        assert_eq!(oac.requested_scopes(&oauth_api()), [
+            "calendar", "email", "openid"
+        ]);
+    }
+
+    #[test]
+    fn overrides_the_apis_params() {
+        let oac = oauth_connection(json!({
+            "authorizationParams": [
+                { "key": "prompt", "value": "select_account" },
+                { "key": "login_hint", "value": "someone@example.com" },
+            ],
+            "extraTokenParams": [{ "key": "audience", "value": "chappaai" }],
+        }));
+
+        assert_eq!(
+            oac.authorization_params(&oauth_api()),
+            params(&[
+                ("access_type", "offline"),
+                ("prompt", "select_account"),
+                ("login_hint", "someone@example.com"),
+            ])
+        );
+        assert_eq!(
+            oac.extra_token_params(&oauth_api()),
+            params(&[("audience", "chappaai")])
+        );
+    }
+
+    #[test]
+    fn sends_the_apis_params_without_params_of_its_own() {
+        let oac = oauth_connection(json!({}));
+
+        assert_eq!(
+            oac.authorization_params(&oauth_api()),
+            params(&[("access_type", "offline"), ("prompt", "consent")])
+        );
+    }
+
+    #[test]
+    fn rejects_params_the_operator_sets() {
+        for key in ["redirect_uri", "state", "code_challenge_method"] {
+            let oac = oauth_connection(json!({ "authorizationParams": [{ "key": key, "value": "x" }] }));
+            assert!(
+                matches!(oac.validate(&Config::default()), Err(Error::InvalidSpec(_))),
+                "{}",
+                key
+            );
+        }
+
+        let oac = oauth_connection(json!({ "extraTokenParams": [{ "key": "grant_type", "value": "x" }] }));
+        assert!(matches!(
+            oac.validate(&Config::default()),
+            Err(Error::InvalidSpec(_))
+        ));
+
+        let oac = oauth_connection(json!({ "authorizationParams": [{ "key": "prompt", "value": "none" }] }));
+        assert!(oac.validate(&Config::default()).is_ok());
+    }
+
+    #[test]
+    fn rejects_copies_which_would_replace_the_token_secret() {
+        let validate = |secret_targets: serde_json::Value| {
+            let mut oac = oauth_connection(json!({ "secretTargets": secret_targets }));
+            oac.metadata.namespace = Some(String::from("default"));
+            oac.validate_secret_targets(&Config::default())
+        };
+
+        for target in [
+            json!({ "namespace": "default" }),
+            json!({ "namespace": "default", "name": "chappaai-google" }),
+        ] {
+            assert!(matches!(validate(json!([target])), Err(Error::InvalidSpec(_))));
+        }
+
+        assert!(validate(json!([{ "namespace": "default", "name": "google-token" }])).is_ok());
+        assert!(validate(json!([{ "namespace": "team", "name": "chappaai-google" }])).is_ok());
+    }
+}
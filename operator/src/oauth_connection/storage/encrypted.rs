@@ -0,0 +1,194 @@
+use super::{
+    envelope::{Envelope, KeyRing},
+    secret::SecretStorage,
+    secrets, StoredToken, TokenStorage,
+};
+use crate::{
+    config::Config,
+    oauth_connection::{token::owned_secret, OAuthConnection},
+    Error, Result,
+};
+use async_trait::async_trait;
+use k8s_openapi::ByteString;
+use kube::{
+    api::{Patch, PatchParams},
+    Client, ResourceExt,
+};
+use std::{collections::BTreeMap, sync::Arc};
+use tracing::info;
+
+/// Annotation of the Secret naming the key its data key is encrypted with
+const KEY_ID_ANNOTATION: &str = "chappaai.dev/encryption-key-id";
+const ENCRYPTED_KEY_KEY: &str = "encryptedKey";
+const ENCRYPTED_TOKEN_KEY: &str = "encryptedToken";
+
+/// Holds the token in a Secret, envelope encrypted with a key from the operator's encryption
+/// Secret, for clusters which don't encrypt Secrets at rest. The connection's namespace and name
+/// are authenticated alongside the token, so that it can't be moved to another connection's Secret.
+pub(crate) struct EncryptedSecretStorage {
+    client: Client,
+    config: Arc<Config>,
+    secret: SecretStorage,
+}
+
+impl EncryptedSecretStorage {
+    pub fn new(client: Client, config: Arc<Config>) -> Self {
+        EncryptedSecretStorage {
+            secret: SecretStorage::new(client.clone(), config.clone()),
+            client,
+            config,
+        }
+    }
+
+    /// The envelope held in the connection's Secret, and the Secret's resource version
+    async fn envelope(
+        &self,
+        oauth_connection: &OAuthConnection,
+    ) -> Result<Option<(Envelope, Option<String>)>> {
+        let secret_name = self.config.secret_name(&oauth_connection.name());
+
+        let secret = match secrets(&self.client, oauth_connection)
+            .get_opt(&secret_name)
+            .await?
+        {
+            Some(secret) => secret,
+            None => return Ok(None),
+        };
+
+        let resource_version = secret.resource_version();
+        let key_id = secret
+            .annotations()
+            .get(KEY_ID_ANNOTATION)
+            .cloned()
+            .ok_or_else(|| {
+                Error::TokenStorageFailed(format!("Secret {} has no encryption key ID", secret_name))
+            })?;
+
+        let mut data = secret.data.unwrap_or_default();
+        let mut take = |key: &str| {
+            data.remove(key)
+                .map(|ByteString(value)| value)
+                .ok_or_else(|| Error::TokenStorageFailed(format!("Secret {} has no {}", secret_name, key)))
+        };
+
+        let envelope = Envelope {
+            key_id,
+            encrypted_key: take(ENCRYPTED_KEY_KEY)?,
+            ciphertext: take(ENCRYPTED_TOKEN_KEY)?,
+        };
+
+        Ok(Some((envelope, resource_version)))
+    }
+
+    fn decrypt(
+        &self,
+        key_ring: &KeyRing,
+        oauth_connection: &OAuthConnection,
+        envelope: &Envelope,
+    ) -> Result<StoredToken> {
+        let plaintext = key_ring.open(&aad(oauth_connection), envelope)?;
+        let string_data: BTreeMap<String, String> =
+            serde_json::from_slice(&plaintext).map_err(Error::SerializationError)?;
+
+        StoredToken::from_string_data(&string_data)
+    }
+
+    /// Writes the token encrypted with the active key. With a resource version, the write fails if
+    /// the Secret has changed since.
+    async fn write(
+        &self,
+        key_ring: &KeyRing,
+        oauth_connection: &OAuthConnection,
+        token: &StoredToken,
+        resource_version: Option<String>,
+    ) -> Result<()> {
+        let secret_name = self.config.secret_name(&oauth_connection.name());
+
+        let plaintext = serde_json::to_vec(&token.string_data()).map_err(Error::SerializationError)?;
+        let envelope = key_ring.seal(&aad(oauth_connection), &plaintext)?;
+
+        let mut secret = owned_secret(oauth_connection, &secret_name)?;
+        secret.metadata.resource_version = resource_version;
+        secret.metadata.annotations =
+            Some(BTreeMap::from([(KEY_ID_ANNOTATION.to_string(), envelope.key_id)]));
+        secret.data = Some(BTreeMap::from([
+            (ENCRYPTED_KEY_KEY.to_string(), ByteString(envelope.encrypted_key)),
+            (ENCRYPTED_TOKEN_KEY.to_string(), ByteString(envelope.ciphertext)),
+        ]));
+
+        secrets(&self.client, oauth_connection)
+            .patch(
+                &secret_name,
+                &PatchParams::apply(&self.config.field_manager),
+                &Patch::Apply(secret),
+            )
+            .await
+            .map_err(|e| Error::TokenStorageFailed(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+fn aad(oauth_connection: &OAuthConnection) -> Vec<u8> {
+    format!(
+        "{}/{}",
+        oauth_connection.namespace().unwrap_or_default(),
+        oauth_connection.name()
+    )
+    .into_bytes()
+}
+
+#[async_trait]
+impl TokenStorage for EncryptedSecretStorage {
+    async fn load(&self, oauth_connection: &OAuthConnection) -> Result<Option<StoredToken>> {
+        let (envelope, _) = match self.envelope(oauth_connection).await? {
+            Some(envelope) => envelope,
+            None => return Ok(None),
+        };
+
+        let key_ring = KeyRing::load(&self.client, &self.config).await?;
+
+        self.decrypt(&key_ring, oauth_connection, &envelope).map(Some)
+    }
+
+    async fn store(&self, oauth_connection: &OAuthConnection, token: &StoredToken) -> Result<()> {
+        let key_ring = KeyRing::load(&self.client, &self.config).await?;
+
+        self.write(&key_ring, oauth_connection, token, None).await
+    }
+
+    async fn delete(&self, oauth_connection: &OAuthConnection) -> Result<()> {
+        self.secret.delete(oauth_connection).await
+    }
+
+    /// Re-encrypts a token whose data key is encrypted with a key other than the active one. The
+    /// write is conditional, so that a token refreshed meanwhile isn't replaced by the one read.
+    async fn rotate(&self, oauth_connection: &OAuthConnection) -> Result<()> {
+        let (envelope, resource_version) = match self.envelope(oauth_connection).await? {
+            Some(envelope) => envelope,
+            None => return Ok(()),
+        };
+
+        let key_ring = KeyRing::load(&self.client, &self.config).await?;
+        if envelope.key_id == key_ring.active_key_id() {
+            return Ok(());
+        }
+
+        let token = self.decrypt(&key_ring, oauth_connection, &envelope)?;
+        self.write(&key_ring, oauth_connection, &token, resource_version)
+            .await?;
+
+        info!(
+            "Re-encrypted token of OAuthConnection {} from key {} to {}",
+            oauth_connection.name(),
+            envelope.key_id,
+            key_ring.active_key_id()
+        );
+
+        Ok(())
+    }
+
+    fn secret_name(&self, oauth_connection: &OAuthConnection) -> Option<String> {
+        self.secret.secret_name(oauth_connection)
+    }
+}
@@ -0,0 +1,245 @@
+//! Envelope encryption with AES-256-GCM: each token is encrypted with a data key of its own, which
+//! is in turn encrypted with a key from the operator's encryption Secret. Keys are identified by
+//! their key in the Secret, so that a new key can be introduced while tokens encrypted with the
+//! old one are still read.
+
+use crate::{config::Config, Error, Result};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{Api, Client};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use std::collections::BTreeMap;
+
+const KEY_LEN: usize = 32;
+
+/// A token encrypted with a data key, and the data key encrypted with the key `key_id`
+pub(crate) struct Envelope {
+    pub key_id: String,
+    pub encrypted_key: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// The keys of the encryption Secret, and which of them encrypts new tokens
+pub(crate) struct KeyRing {
+    active_key_id: String,
+    keys: BTreeMap<String, Vec<u8>>,
+}
+
+impl KeyRing {
+    /// Reads the keys from the encryption Secret, in the operator's namespace. Keys may be the 32
+    /// bytes themselves, or base64 encoded.
+    pub async fn load(client: &Client, config: &Config) -> Result<Self> {
+        let (secret_name, active_key_id) = match (&config.encryption_secret, &config.encryption_key_id) {
+            (Some(secret_name), Some(active_key_id)) => (secret_name, active_key_id),
+            _ => {
+                return Err(Error::TokenStorageFailed(String::from(
+                    "encryptionSecret and encryptionKeyId are required to encrypt tokens",
+                )))
+            }
+        };
+
+        let secret = Api::<Secret>::default_namespaced(client.clone())
+            .get(secret_name)
+            .await?;
+
+        let keys = secret
+            .data
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key_id, key)| match decode_key(key.0) {
+                Some(key) => Ok((key_id, key)),
+                None => Err(Error::TokenStorageFailed(format!(
+                    "Encryption key {} must be 32 bytes",
+                    key_id
+                ))),
+            })
+            .collect::<Result<BTreeMap<_, _>>>()?;
+
+        if !keys.contains_key(active_key_id) {
+            return Err(Error::TokenStorageFailed(format!(
+                "Secret {} has no encryption key {}",
+                secret_name, active_key_id
+            )));
+        }
+
+        Ok(KeyRing {
+            active_key_id: active_key_id.clone(),
+            keys,
+        })
+    }
+
+    pub fn active_key_id(&self) -> &str {
+        &self.active_key_id
+    }
+
+    /// Encrypts `plaintext` with a new data key, wrapped with the active key
+    pub fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Result<Envelope> {
+        let mut data_key = [0u8; KEY_LEN];
+        SystemRandom::new()
+            .fill(&mut data_key)
+            .map_err(|_| Error::TokenStorageFailed(String::from("Failed to generate a data key")))?;
+
+        Ok(Envelope {
+            key_id: self.active_key_id.clone(),
+            encrypted_key: seal(&self.keys[&self.active_key_id], aad, &data_key)?,
+            ciphertext: seal(&data_key, aad, plaintext)?,
+        })
+    }
+
+    /// Decrypts an envelope sealed with any of the keys
+    pub fn open(&self, aad: &[u8], envelope: &Envelope) -> Result<Vec<u8>> {
+        let key = self.keys.get(&envelope.key_id).ok_or_else(|| {
+            Error::TokenStorageFailed(format!("No encryption key {} to decrypt with", envelope.key_id))
+        })?;
+
+        let data_key = open(key, aad, &envelope.encrypted_key)?;
+        open(&data_key, aad, &envelope.ciphertext)
+    }
+}
+
+fn decode_key(key: Vec<u8>) -> Option<Vec<u8>> {
+    if key.len() == KEY_LEN {
+        return Some(key);
+    }
+
+    std::str::from_utf8(&key)
+        .ok()
+        .and_then(|key| base64::decode(key.trim()).ok())
+        .filter(|key| key.len() == KEY_LEN)
+}
+
+fn aead_key(key: &[u8]) -> Result<LessSafeKey> {
+    UnboundKey::new(&AES_256_GCM, key)
+        .map(LessSafeKey::new)
+        .map_err(|_| Error::TokenStorageFailed(String::from("Invalid encryption key")))
+}
+
+/// Encrypts with a random nonce, which is prepended to the ciphertext
+fn seal(key: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| Error::TokenStorageFailed(String::from("Failed to generate a nonce")))?;
+
+    let mut ciphertext = plaintext.to_vec();
+    aead_key(key)?
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(aad),
+            &mut ciphertext,
+        )
+        .map_err(|_| Error::TokenStorageFailed(String::from("Failed to encrypt")))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.append(&mut ciphertext);
+
+    Ok(sealed)
+}
+
+fn open(key: &[u8], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_LEN {
+        return Err(Error::TokenStorageFailed(String::from(
+            "Encrypted value is truncated",
+        )));
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(nonce)
+        .map_err(|_| Error::TokenStorageFailed(String::from("Invalid nonce")))?;
+
+    let mut plaintext = ciphertext.to_vec();
+    let length = aead_key(key)?
+        .open_in_place(nonce, Aad::from(aad), &mut plaintext)
+        .map_err(|_| Error::TokenStorageFailed(String::from("Failed to decrypt")))?
+        .len();
+    plaintext.truncate(length);
+
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_ring() -> KeyRing {
+        KeyRing {
+            active_key_id: String::from("2"),
+            keys: BTreeMap::from([
+                (String::from("1"), vec![1; KEY_LEN]),
+                (String::from("2"), vec![2; KEY_LEN]),
+            ]),
+        }
+    }
+
+    #[test]
+    fn seals_with_the_active_key_and_opens() {
+        let key_ring = key_ring();
+        let envelope = key_ring.seal(b"default/github", b"token").unwrap();
+
+        assert_eq!(envelope.key_id, "2");
+        assert_ne!(envelope.ciphertext, b"token");
+        assert_eq!(key_ring.open(b"default/github", &envelope).unwrap(), b"token");
+    }
+
+    #[test]
+    fn opens_envelopes_sealed_with_an_inactive_key() {
+        let envelope = KeyRing {
+            active_key_id: String::from("1"),
+            ..key_ring()
+        }
+        .seal(b"default/github", b"token")
+        .unwrap();
+
+        assert_eq!(key_ring().open(b"default/github", &envelope).unwrap(), b"token");
+    }
+
+    #[test]
+    fn rejects_a_different_aad() {
+        let key_ring = key_ring();
+        let envelope = key_ring.seal(b"default/github", b"token").unwrap();
+
+        assert!(key_ring.open(b"default/gitlab", &envelope).is_err());
+    }
+
+    #[test]
+    fn rejects_an_unknown_key_id() {
+        let key_ring = key_ring();
+        let envelope = Envelope {
+            key_id: String::from("3"),
+            ..key_ring.seal(b"default/github", b"token").unwrap()
+        };
+
+        assert!(key_ring.open(b"default/github", &envelope).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_envelopes() {
+        let key_ring = key_ring();
+        let envelope = key_ring.seal(b"default/github", b"token").unwrap();
+
+        let truncated_ciphertext = Envelope {
+            key_id: envelope.key_id.clone(),
+            encrypted_key: envelope.encrypted_key.clone(),
+            ciphertext: envelope.ciphertext[..envelope.ciphertext.len() - 1].to_vec(),
+        };
+        assert!(key_ring.open(b"default/github", &truncated_ciphertext).is_err());
+
+        let truncated_nonce = Envelope {
+            encrypted_key: envelope.encrypted_key[..NONCE_LEN - 1].to_vec(),
+            ..envelope
+        };
+        assert!(key_ring.open(b"default/github", &truncated_nonce).is_err());
+    }
+
+    #[test]
+    fn decodes_raw_and_base64_keys() {
+        assert_eq!(decode_key(vec![7; KEY_LEN]), Some(vec![7; KEY_LEN]));
+        assert_eq!(
+            decode_key(format!("{}\n", base64::encode([7; KEY_LEN])).into_bytes()),
+            Some(vec![7; KEY_LEN])
+        );
+        assert_eq!(decode_key(vec![7; 16]), None);
+    }
+}
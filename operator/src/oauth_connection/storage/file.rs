@@ -0,0 +1,95 @@
+use super::{StoredToken, TokenStorage};
+use crate::{oauth_connection::OAuthConnection, Error, Result};
+use async_trait::async_trait;
+use kube::ResourceExt;
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use tokio::{fs, io::AsyncWriteExt};
+
+/// Holds the token as JSON in a file beneath the token directory, at `<namespace>/<name>.json`,
+/// so that it never reaches the API server. Replicas must share the directory's volume.
+pub(crate) struct FileStorage {
+    directory: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(directory: PathBuf) -> Self {
+        FileStorage { directory }
+    }
+
+    fn path(&self, oauth_connection: &OAuthConnection) -> PathBuf {
+        self.directory
+            .join(oauth_connection.namespace().unwrap_or_default())
+            .join(format!("{}.json", oauth_connection.name()))
+    }
+}
+
+fn storage_error(path: &Path, error: std::io::Error) -> Error {
+    Error::TokenStorageFailed(format!("{}: {}", path.display(), error))
+}
+
+#[async_trait]
+impl TokenStorage for FileStorage {
+    async fn load(&self, oauth_connection: &OAuthConnection) -> Result<Option<StoredToken>> {
+        let path = self.path(oauth_connection);
+
+        let contents = match fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(storage_error(&path, e)),
+        };
+
+        let string_data: BTreeMap<String, String> =
+            serde_json::from_slice(&contents).map_err(Error::SerializationError)?;
+
+        StoredToken::from_string_data(&string_data).map(Some)
+    }
+
+    /// Writes to a temporary file which replaces the token, so that readers never see a partial write
+    async fn store(&self, oauth_connection: &OAuthConnection, token: &StoredToken) -> Result<()> {
+        let path = self.path(oauth_connection);
+        let partial = path.with_extension("json.partial");
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .map_err(|e| storage_error(parent, e))?;
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&partial)
+            .await
+            .map_err(|e| storage_error(&partial, e))?;
+
+        let contents = serde_json::to_vec(&token.string_data()).map_err(Error::SerializationError)?;
+        file.write_all(&contents)
+            .await
+            .map_err(|e| storage_error(&partial, e))?;
+        file.sync_all().await.map_err(|e| storage_error(&partial, e))?;
+
+        fs::rename(&partial, &path)
+            .await
+            .map_err(|e| storage_error(&path, e))
+    }
+
+    async fn delete(&self, oauth_connection: &OAuthConnection) -> Result<()> {
+        let path = self.path(oauth_connection);
+
+        match fs::remove_file(&path).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(storage_error(&path, e)),
+        }
+    }
+
+    fn secret_name(&self, _oauth_connection: &OAuthConnection) -> Option<String> {
+        None
+    }
+}
@@ -0,0 +1,57 @@
+//! Backends holding connections' tokens, chosen by each connection's `storage`. Every path which
+//! reads or writes a token goes through these, so that backends are interchangeable.
+
+use super::{token::StoredToken, OAuthConnection, TokenStorageBackend};
+use crate::{config::Config, Result};
+use async_trait::async_trait;
+use k8s_openapi::api::core::v1::Secret;
+use kube::{Api, Client, ResourceExt};
+use std::sync::Arc;
+
+mod encrypted;
+mod envelope;
+mod file;
+mod secret;
+
+#[async_trait]
+pub(crate) trait TokenStorage: Send + Sync {
+    /// The connection's token, if it has one
+    async fn load(&self, oauth_connection: &OAuthConnection) -> Result<Option<StoredToken>>;
+
+    /// Stores the connection's token, replacing any it had
+    async fn store(&self, oauth_connection: &OAuthConnection, token: &StoredToken) -> Result<()>;
+
+    /// Removes the connection's token, if it has one
+    async fn delete(&self, oauth_connection: &OAuthConnection) -> Result<()>;
+
+    /// Re-encrypts the token with the current key, for backends which encrypt it
+    async fn rotate(&self, _oauth_connection: &OAuthConnection) -> Result<()> {
+        Ok(())
+    }
+
+    /// Name of the Secret holding the token, for backends which keep it in one
+    fn secret_name(&self, oauth_connection: &OAuthConnection) -> Option<String>;
+}
+
+/// The storage the connection has chosen for its token
+pub(crate) fn for_connection(
+    client: &Client,
+    config: &Arc<Config>,
+    oauth_connection: &OAuthConnection,
+) -> Box<dyn TokenStorage> {
+    match oauth_connection.spec.storage {
+        TokenStorageBackend::Secret => Box::new(secret::SecretStorage::new(client.clone(), config.clone())),
+        TokenStorageBackend::EncryptedSecret => Box::new(encrypted::EncryptedSecretStorage::new(
+            client.clone(),
+            config.clone(),
+        )),
+        TokenStorageBackend::File => Box::new(file::FileStorage::new(config.token_directory.clone())),
+    }
+}
+
+fn secrets(client: &Client, oauth_connection: &OAuthConnection) -> Api<Secret> {
+    match oauth_connection.namespace() {
+        Some(namespace) => Api::namespaced(client.clone(), &namespace),
+        None => Api::default_namespaced(client.clone()),
+    }
+}
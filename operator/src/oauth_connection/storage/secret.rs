@@ -0,0 +1,65 @@
+use super::{secrets, StoredToken, TokenStorage};
+use crate::{config::Config, oauth_connection::OAuthConnection, Error, Result};
+use async_trait::async_trait;
+use kube::{
+    api::{DeleteParams, Patch, PatchParams},
+    Client, ResourceExt,
+};
+use std::sync::Arc;
+
+/// Holds the token in a Secret, readable by anything allowed to read the Secret
+pub(crate) struct SecretStorage {
+    client: Client,
+    config: Arc<Config>,
+}
+
+impl SecretStorage {
+    pub fn new(client: Client, config: Arc<Config>) -> Self {
+        SecretStorage { client, config }
+    }
+}
+
+#[async_trait]
+impl TokenStorage for SecretStorage {
+    async fn load(&self, oauth_connection: &OAuthConnection) -> Result<Option<StoredToken>> {
+        let secret_name = self.config.secret_name(&oauth_connection.name());
+
+        secrets(&self.client, oauth_connection)
+            .get_opt(&secret_name)
+            .await?
+            .map(|secret| StoredToken::from_secret(&secret))
+            .transpose()
+    }
+
+    async fn store(&self, oauth_connection: &OAuthConnection, token: &StoredToken) -> Result<()> {
+        let secret_name = self.config.secret_name(&oauth_connection.name());
+
+        secrets(&self.client, oauth_connection)
+            .patch(
+                &secret_name,
+                &PatchParams::apply(&self.config.field_manager),
+                &Patch::Apply(token.to_secret(oauth_connection, &secret_name)?),
+            )
+            .await
+            .map_err(|e| Error::TokenStorageFailed(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, oauth_connection: &OAuthConnection) -> Result<()> {
+        let secret_name = self.config.secret_name(&oauth_connection.name());
+
+        match secrets(&self.client, oauth_connection)
+            .delete(&secret_name, &DeleteParams::default())
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(kube::Error::Api(response)) if response.code == 404 => Ok(()),
+            Err(e) => Err(Error::KubeError(e)),
+        }
+    }
+
+    fn secret_name(&self, oauth_connection: &OAuthConnection) -> Option<String> {
+        Some(self.config.secret_name(&oauth_connection.name()))
+    }
+}
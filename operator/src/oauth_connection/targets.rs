@@ -0,0 +1,209 @@
+//! Copies of a connection's token Secret in the namespaces listed by its `secretTargets`. Owner
+//! references can't cross namespaces, so copies are labelled with the connection they belong to,
+//! and removed by the controller through a finalizer instead. Namespaces other than the
+//! connection's own must opt in to being targeted, and Secrets which aren't copies are never
+//! written to.
+
+use super::{token::StoredToken, OAuthConnection, SecretTarget};
+use crate::{config::Config, Error, Result};
+use k8s_openapi::api::core::v1::{Namespace, Secret};
+use kube::{
+    api::{Api, DeleteParams, ListParams, Patch, PatchParams, PostParams},
+    core::ObjectMeta,
+    Client, ResourceExt,
+};
+use std::collections::BTreeMap;
+
+/// Held by connections with copies to remove before they can be deleted
+pub(crate) const FINALIZER: &str = "chappaai.dev/secret-targets";
+
+const CONNECTION_LABEL: &str = "chappaai.dev/connection";
+const CONNECTION_NAMESPACE_LABEL: &str = "chappaai.dev/connection-namespace";
+const MANAGED_BY_LABEL: &str = "app.kubernetes.io/managed-by";
+
+/// Label of namespaces which connections in other namespaces may write to, when `true`
+pub(crate) const ACCEPT_TARGETS_LABEL: &str = "chappaai.dev/accept-targets";
+
+/// Checks that the connection may write to `namespace`, which is either its own or has opted in
+pub(crate) async fn check_namespace(
+    client: &Client,
+    oauth_connection: &OAuthConnection,
+    namespace: &str,
+) -> Result<()> {
+    if oauth_connection.metadata.namespace.as_deref() == Some(namespace) {
+        return Ok(());
+    }
+
+    let accepted = Api::<Namespace>::all(client.clone())
+        .get_opt(namespace)
+        .await?
+        .is_some_and(|namespace| {
+            namespace.labels().get(ACCEPT_TARGETS_LABEL).map(String::as_str) == Some("true")
+        });
+
+    match accepted {
+        true => Ok(()),
+        false => Err(Error::Forbidden(format!(
+            "namespace {} must be labelled {}=true to be targeted by OAuthConnection {}",
+            namespace,
+            ACCEPT_TARGETS_LABEL,
+            oauth_connection.name()
+        ))),
+    }
+}
+
+/// Checks every namespace the connection targets, with copies of its token or workloads to reload
+pub(crate) async fn check_target_namespaces(
+    client: &Client,
+    oauth_connection: &OAuthConnection,
+) -> Result<()> {
+    for target in &oauth_connection.spec.secret_targets {
+        check_namespace(client, oauth_connection, &target.namespace).await?;
+    }
+
+    for namespace in oauth_connection
+        .spec
+        .reload_targets
+        .iter()
+        .filter_map(|target| target.namespace.as_ref())
+    {
+        check_namespace(client, oauth_connection, namespace).await?;
+    }
+
+    Ok(())
+}
+
+fn labels(oauth_connection: &OAuthConnection) -> BTreeMap<String, String> {
+    BTreeMap::from([
+        (CONNECTION_LABEL.to_string(), oauth_connection.name()),
+        (
+            CONNECTION_NAMESPACE_LABEL.to_string(),
+            oauth_connection.namespace().unwrap_or_default(),
+        ),
+        (MANAGED_BY_LABEL.to_string(), String::from("chappaai")),
+    ])
+}
+
+/// The name of the copy kept for a target, which defaults to that of the token Secret
+pub(crate) fn target_name(
+    config: &Config,
+    oauth_connection: &OAuthConnection,
+    target: &SecretTarget,
+) -> String {
+    target
+        .name
+        .clone()
+        .unwrap_or_else(|| config.secret_name(&oauth_connection.name()))
+}
+
+/// Writes the token to a copy in every target namespace
+pub(crate) async fn replicate(
+    client: &Client,
+    config: &Config,
+    oauth_connection: &OAuthConnection,
+    token: &StoredToken,
+) -> Result<()> {
+    oauth_connection.validate_secret_targets(config)?;
+
+    for target in &oauth_connection.spec.secret_targets {
+        let name = target_name(config, oauth_connection, target);
+        check_namespace(client, oauth_connection, &target.namespace).await?;
+
+        let labels = labels(oauth_connection);
+        let mut copy = Secret {
+            metadata: ObjectMeta {
+                name: Some(name.clone()),
+                namespace: Some(target.namespace.clone()),
+                labels: Some(labels.clone()),
+                ..ObjectMeta::default()
+            },
+            immutable: Some(false),
+            string_data: Some(token.string_data()),
+            ..Secret::default()
+        };
+
+        let secrets = Api::<Secret>::namespaced(client.clone(), &target.namespace);
+        let copy_error = |e: kube::Error| {
+            Error::TokenStorageFailed(format!(
+                "Failed to copy token to {}/{}: {}",
+                target.namespace, name, e
+            ))
+        };
+
+        // Updates are conditional on the copy being unchanged since it was found to be ours
+        match secrets.get_opt(&name).await? {
+            None => {
+                let params = PostParams {
+                    field_manager: Some(config.field_manager.clone()),
+                    ..PostParams::default()
+                };
+                secrets.create(&params, &copy).await.map_err(copy_error)?;
+            }
+            Some(existing)
+                if labels
+                    .iter()
+                    .all(|(key, value)| existing.labels().get(key) == Some(value)) =>
+            {
+                copy.metadata.resource_version = existing.resource_version();
+                secrets
+                    .patch(
+                        &name,
+                        &PatchParams::apply(&config.field_manager),
+                        &Patch::Apply(copy),
+                    )
+                    .await
+                    .map_err(copy_error)?;
+            }
+            Some(_) => {
+                return Err(Error::TokenStorageFailed(format!(
+                    "Secret {}/{} already exists, and isn't a copy of OAuthConnection {}",
+                    target.namespace,
+                    name,
+                    oauth_connection.name()
+                )))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes the connection's copies which aren't in `keep`, found by their labels
+pub(crate) async fn prune(
+    client: &Client,
+    config: &Config,
+    oauth_connection: &OAuthConnection,
+    keep: &[SecretTarget],
+) -> Result<()> {
+    let selector = labels(oauth_connection)
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let copies = Api::<Secret>::all(client.clone())
+        .list(&ListParams::default().labels(&selector))
+        .await?;
+
+    for copy in copies {
+        let namespace = copy.namespace().unwrap_or_default();
+        let name = copy.name();
+
+        let kept = keep.iter().any(|target| {
+            target.namespace == namespace && target_name(config, oauth_connection, target) == name
+        });
+
+        if !kept {
+            match Api::<Secret>::namespaced(client.clone(), &namespace)
+                .delete(&name, &DeleteParams::default())
+                .await
+            {
+                Ok(_) => {}
+                Err(kube::Error::Api(response)) if response.code == 404 => {}
+                Err(e) => return Err(Error::KubeError(e)),
+            }
+        }
+    }
+
+    Ok(())
+}
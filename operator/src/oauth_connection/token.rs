@@ -0,0 +1,335 @@
+use super::{
+    api::oauth_basic_client, reload, storage, targets, token_client, OAuthConnection, OAuthConnectionPhase,
+};
+use crate::{
+    api_version,
+    config::Config,
+    kubernetes::{controller::object_key, get_string_value},
+    ApplicationState, Error, OAuthApi, Result,
+};
+use chrono::{DateTime, Duration, Utc};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{
+    api::{Patch, PatchParams},
+    core::ObjectMeta,
+    Api, Resource, ResourceExt,
+};
+use oauth2::{basic::BasicTokenResponse, RefreshToken, TokenResponse};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+use tracing::{info, info_span, warn, Instrument};
+
+pub(crate) const ACCESS_TOKEN_KEY: &str = "accessToken";
+pub(crate) const REFRESH_TOKEN_KEY: &str = "refreshToken";
+pub(crate) const EXPIRES_AT_KEY: &str = "expiresAt";
+
+/// Tokens are refreshed this long before they expire, so that none are sent as they expire
+const EXPIRY_MARGIN_SECONDS: i64 = 30;
+
+/// A connection's token, as held by its storage
+#[derive(Clone)]
+pub(crate) struct StoredToken {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl StoredToken {
+    /// Keeps the previous refresh token when the provider doesn't issue a new one
+    pub fn from_response(response: &BasicTokenResponse, previous: Option<&StoredToken>) -> Self {
+        StoredToken {
+            access_token: response.access_token().secret().to_string(),
+            refresh_token: response
+                .refresh_token()
+                .map(|token| token.secret().to_string())
+                .or_else(|| previous.and_then(|previous| previous.refresh_token.clone())),
+            expires_at: response
+                .expires_in()
+                .and_then(|expires_in| Duration::from_std(expires_in).ok())
+                .map(|expires_in| Utc::now() + expires_in),
+        }
+    }
+
+    pub fn from_secret(secret: &Secret) -> Result<Self> {
+        Ok(StoredToken {
+            access_token: get_string_value(secret, ACCESS_TOKEN_KEY)?,
+            refresh_token: get_string_value(secret, REFRESH_TOKEN_KEY).ok(),
+            expires_at: get_string_value(secret, EXPIRES_AT_KEY)
+                .ok()
+                .and_then(|expires_at| DateTime::parse_from_rfc3339(&expires_at).ok())
+                .map(|expires_at| expires_at.with_timezone(&Utc)),
+        })
+    }
+
+    /// Whether the token has expired, or is about to
+    pub fn expiring(&self) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at - Duration::seconds(EXPIRY_MARGIN_SECONDS) <= Utc::now())
+            .unwrap_or(false)
+    }
+
+    /// The keys of a Secret holding this token
+    pub fn string_data(&self) -> BTreeMap<String, String> {
+        let mut string_data = BTreeMap::from([(ACCESS_TOKEN_KEY.to_string(), self.access_token.clone())]);
+        if let Some(refresh_token) = &self.refresh_token {
+            string_data.insert(REFRESH_TOKEN_KEY.to_string(), refresh_token.clone());
+        }
+        if let Some(expires_at) = &self.expires_at {
+            string_data.insert(EXPIRES_AT_KEY.to_string(), expires_at.to_rfc3339());
+        }
+
+        string_data
+    }
+
+    /// The Secret holding this token
+    pub fn to_secret(&self, oauth_connection: &OAuthConnection, name: &str) -> Result<Secret> {
+        Ok(Secret {
+            string_data: Some(self.string_data()),
+            ..owned_secret(oauth_connection, name)?
+        })
+    }
+
+    /// Reads a token from the keys written by `string_data`
+    pub fn from_string_data(string_data: &BTreeMap<String, String>) -> Result<Self> {
+        Ok(StoredToken {
+            access_token: string_data
+                .get(ACCESS_TOKEN_KEY)
+                .cloned()
+                .ok_or_else(|| Error::GenericError(format!("Token has no {}", ACCESS_TOKEN_KEY)))?,
+            refresh_token: string_data.get(REFRESH_TOKEN_KEY).cloned(),
+            expires_at: string_data
+                .get(EXPIRES_AT_KEY)
+                .and_then(|expires_at| DateTime::parse_from_rfc3339(expires_at).ok())
+                .map(|expires_at| expires_at.with_timezone(&Utc)),
+        })
+    }
+}
+
+/// An empty Secret owned by the connection, so that it is deleted alongside it
+pub(crate) fn owned_secret(oauth_connection: &OAuthConnection, name: &str) -> Result<Secret> {
+    let owner_ref = oauth_connection.controller_owner_ref(&()).ok_or_else(|| {
+        Error::GenericError(format!("OAuthConnection {} has no uid", oauth_connection.name()))
+    })?;
+
+    Ok(Secret {
+        metadata: ObjectMeta {
+            name: Some(name.to_string()),
+            namespace: oauth_connection.namespace(),
+            owner_references: Some(vec![owner_ref]),
+            ..ObjectMeta::default()
+        },
+        immutable: Some(false),
+        ..Secret::default()
+    })
+}
+
+fn secrets(state: &ApplicationState, oauth_connection: &OAuthConnection) -> Api<Secret> {
+    match oauth_connection.namespace() {
+        Some(namespace) => Api::namespaced(state.client.clone(), &namespace),
+        None => Api::default_namespaced(state.client.clone()),
+    }
+}
+
+/// Reads the connection's current token from its storage
+pub(crate) async fn load(
+    state: &ApplicationState,
+    oauth_connection: &OAuthConnection,
+) -> Result<StoredToken> {
+    storage::for_connection(&state.client, &state.config, oauth_connection)
+        .load(oauth_connection)
+        .await?
+        .ok_or_else(|| Error::NotConnected(oauth_connection.name()))
+}
+
+/// The token of a connected connection, refreshed first if it is about to expire
+pub(crate) async fn current(
+    state: &ApplicationState,
+    oauth_connection: &OAuthConnection,
+    oauth_api: &OAuthApi,
+) -> Result<StoredToken> {
+    if !matches!(
+        oauth_connection
+            .status
+            .as_ref()
+            .and_then(|status| status.phase.as_ref()),
+        Some(OAuthConnectionPhase::Connected)
+    ) {
+        return Err(Error::NotConnected(oauth_connection.name()));
+    }
+
+    let token = load(state, oauth_connection).await?;
+
+    match token.refresh_token.is_some() && token.expiring() {
+        true => refresh(state, oauth_connection, oauth_api, &token).await,
+        false => Ok(token),
+    }
+}
+
+/// Field manager of the expiry written by refreshes
+pub(crate) fn refresh_field_manager(config: &Config) -> String {
+    format!("{}-refresh", config.field_manager)
+}
+
+/// Refreshes in progress, keyed by connection, so that concurrent requests which find the same
+/// token rejected only refresh it once. Also held while a token is replaced or revoked, which a
+/// refresh would otherwise overwrite.
+#[derive(Default)]
+pub struct TokenRefreshes {
+    locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl TokenRefreshes {
+    /// The lock of the connection. Locks nobody holds are forgotten, so that those of deleted
+    /// connections don't accumulate.
+    pub(crate) fn lock(&self, oauth_connection: &OAuthConnection) -> Arc<tokio::sync::Mutex<()>> {
+        let mut locks = self.locks.lock().expect("token refreshes lock poisoned");
+        locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+
+        locks
+            .entry(object_key(oauth_connection.namespace(), &oauth_connection.name()))
+            .or_default()
+            .clone()
+    }
+}
+
+/// Exchanges the connection's refresh token for a new token, replacing `stale`, which the
+/// provider rejected or is about to expire. Returns the token refreshed by another request instead,
+/// if there was one.
+pub(crate) async fn refresh(
+    state: &ApplicationState,
+    oauth_connection: &OAuthConnection,
+    oauth_api: &OAuthApi,
+    stale: &StoredToken,
+) -> Result<StoredToken> {
+    let name = oauth_connection.name();
+    let lock = state.refreshes.lock(oauth_connection);
+    let _guard = lock.lock().await;
+
+    let api: Api<OAuthConnection> = match oauth_connection.namespace() {
+        Some(namespace) => Api::namespaced(state.client.clone(), &namespace),
+        None => Api::default_namespaced(state.client.clone()),
+    };
+
+    // The cached connection may predate a disconnect, which a refresh must not undo
+    let live = api.get(&name).await?;
+    if !matches!(
+        live.status.as_ref().and_then(|status| status.phase.as_ref()),
+        Some(OAuthConnectionPhase::Connected)
+    ) {
+        return Err(Error::NotConnected(name));
+    }
+
+    let current = load(state, oauth_connection).await?;
+    if current.access_token != stale.access_token {
+        return Ok(current);
+    }
+
+    let refresh_token = current
+        .refresh_token
+        .clone()
+        .ok_or_else(|| Error::TokenRefreshFailed(format!("OAuthConnection {} has no refresh token", name)))?;
+
+    let (oauth_client, auth_params) = oauth_basic_client(
+        secrets(state, oauth_connection),
+        oauth_connection,
+        oauth_api,
+        oauth_api.get_refresh_url()?,
+        None,
+    )
+    .await?;
+
+    let refresh_token = RefreshToken::new(refresh_token);
+    let response = auth_params
+        .into_iter()
+        .chain(oauth_connection.extra_token_params(oauth_api))
+        .fold(
+            oauth_client.exchange_refresh_token(&refresh_token),
+            |request, (key, value)| request.add_extra_param(key, value),
+        )
+        .request_async(|request| token_client::http_client(oauth_api.token_response(), request))
+        .instrument(info_span!("token_refresh", api = %oauth_api.name(), connection = %name))
+        .await;
+    state.metrics.token_refresh(&oauth_api.name(), response.is_ok());
+    let response = response.map_err(|e| Error::TokenRefreshFailed(token_client::describe(&e)))?;
+
+    let token = StoredToken::from_response(&response, Some(&current));
+
+    storage::for_connection(&state.client, &state.config, oauth_connection)
+        .store(oauth_connection, &token)
+        .await?;
+
+    // Copies and workloads the controller fails to update here are updated when it next reconciles
+    if let Err(e) = targets::replicate(&state.client, &state.config, oauth_connection, &token).await {
+        warn!("{}", e);
+    }
+    if let Err(e) = reload::reload(&state.client, oauth_connection, &token).await {
+        warn!("Failed to reload workloads: {}", e);
+    }
+
+    // Applied by a field manager of its own, so that only the expiry is written, and the phase and
+    // conditions written since the connection was cached are left alone
+    api.patch_status(
+        &name,
+        &PatchParams::apply(&refresh_field_manager(&state.config)).force(),
+        &Patch::Apply(json!({
+            "apiVersion": api_version(),
+            "kind": "OAuthConnection",
+            "status": {
+                "expiresAt": token.expires_at.map(|expires_at| expires_at.to_rfc3339()),
+            },
+        })),
+    )
+    .await?;
+
+    info!("Refreshed token of OAuthConnection {}", name);
+
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oauth_connection(namespace: &str) -> OAuthConnection {
+        let mut oauth_connection = OAuthConnection::new(
+            "github",
+            serde_json::from_value(json!({
+                "api": "github",
+                "credentials": { "secretRef": { "name": "github" } },
+            }))
+            .unwrap(),
+        );
+        oauth_connection.metadata.namespace = Some(namespace.to_string());
+
+        oauth_connection
+    }
+
+    #[test]
+    fn locks_connections_of_the_same_name_separately() {
+        let refreshes = TokenRefreshes::default();
+        let default = refreshes.lock(&oauth_connection("default"));
+
+        assert!(Arc::ptr_eq(
+            &default,
+            &refreshes.lock(&oauth_connection("default"))
+        ));
+        assert!(!Arc::ptr_eq(&default, &refreshes.lock(&oauth_connection("team"))));
+    }
+
+    #[test]
+    fn forgets_locks_nobody_holds() {
+        let refreshes = TokenRefreshes::default();
+        let default = refreshes.lock(&oauth_connection("default"));
+        drop(refreshes.lock(&oauth_connection("team")));
+
+        refreshes.lock(&oauth_connection("other"));
+        let locks = refreshes.locks.lock().unwrap();
+
+        assert!(locks.contains_key("default/github"));
+        assert!(!locks.contains_key("team/github"));
+        drop(default);
+    }
+}
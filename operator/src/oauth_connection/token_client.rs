@@ -0,0 +1,331 @@
+//! The HTTP client for token requests, which reads responses as the API's `tokenResponse` describes
+//! and hands `oauth2` the standard JSON response it parses. Without a mapping, form encoded
+//! responses, such as GitHub's, and lifetimes sent as strings are still understood.
+
+use crate::oauth_api::{TokenContentType, TokenResponseMapping};
+use oauth2::{
+    basic::BasicErrorResponse,
+    http::{
+        header::{HeaderValue, CONTENT_TYPE},
+        HeaderMap, StatusCode,
+    },
+    reqwest::{async_http_client, AsyncHttpClientError, Error},
+    url::form_urlencoded,
+    HttpRequest, HttpResponse, RequestTokenError,
+};
+use serde_json::{json, Map, Value};
+
+/// Sends a token request, normalizing the response
+pub(crate) async fn http_client(
+    mapping: TokenResponseMapping,
+    request: HttpRequest,
+) -> Result<HttpResponse, AsyncHttpClientError> {
+    let response = async_http_client(request).await?;
+
+    normalize(&mapping, response)
+}
+
+/// The response as `oauth2` expects it: a standard JSON token or error response
+fn normalize(
+    mapping: &TokenResponseMapping,
+    response: HttpResponse,
+) -> Result<HttpResponse, AsyncHttpClientError> {
+    let body = parse(mapping, &response)?;
+
+    // Some APIs report errors with a successful status, which `oauth2` would fail to parse as a token
+    let (status_code, body) = match body.get("error") {
+        Some(Value::String(_)) => {
+            let status_code = if response.status_code.is_success() {
+                StatusCode::BAD_REQUEST
+            } else {
+                response.status_code
+            };
+            (status_code, error(&body))
+        }
+        _ if response.status_code.is_success() => (response.status_code, token(mapping, &body)?),
+        _ => return Ok(response),
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    Ok(HttpResponse {
+        status_code,
+        headers,
+        body: serde_json::to_vec(&body).map_err(|e| Error::Other(e.to_string()))?,
+    })
+}
+
+/// The response body as JSON, with form encoded parameters becoming an object's string fields
+fn parse(mapping: &TokenResponseMapping, response: &HttpResponse) -> Result<Value, AsyncHttpClientError> {
+    let content_type = mapping.content_type.unwrap_or_else(|| {
+        match response
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(value) if value.starts_with("application/x-www-form-urlencoded") => TokenContentType::Form,
+            _ => TokenContentType::Json,
+        }
+    });
+
+    match content_type {
+        // The body may hold a token, so only its length and type are reported
+        TokenContentType::Json => serde_json::from_slice(&response.body).map_err(|_| {
+            Error::Other(format!(
+                "Token response of {} bytes is not JSON (Content-Type {})",
+                response.body.len(),
+                response
+                    .headers
+                    .get(CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or("unset")
+            ))
+        }),
+        TokenContentType::Form => Ok(Value::Object(
+            form_urlencoded::parse(&response.body)
+                .map(|(key, value)| (key.into_owned(), Value::String(value.into_owned())))
+                .collect(),
+        )),
+    }
+}
+
+fn error(body: &Value) -> Value {
+    let mut error = Map::new();
+    for field in ["error", "error_description", "error_uri"] {
+        if let Some(value @ Value::String(_)) = body.get(field) {
+            error.insert(field.to_string(), value.clone());
+        }
+    }
+
+    Value::Object(error)
+}
+
+/// A standard token response, from the fields `mapping` points to
+fn token(mapping: &TokenResponseMapping, body: &Value) -> Result<Value, AsyncHttpClientError> {
+    let field = |pointer: &Option<String>, default: &str| {
+        body.pointer(pointer.as_deref().unwrap_or(default))
+            .filter(|value| !value.is_null())
+    };
+
+    let access_token = match field(&mapping.access_token_pointer, "/access_token") {
+        Some(Value::String(access_token)) => access_token,
+        _ => {
+            return Err(Error::Other(format!(
+                "Token response has no access token at {}",
+                mapping.access_token_pointer.as_deref().unwrap_or("/access_token")
+            )))
+        }
+    };
+
+    let token_type = match body.get("token_type") {
+        Some(Value::String(token_type)) => token_type.as_str(),
+        _ => "bearer",
+    };
+
+    let mut token = json!({
+        "access_token": access_token,
+        "token_type": token_type,
+    });
+
+    if let Some(Value::String(refresh_token)) = field(&mapping.refresh_token_pointer, "/refresh_token") {
+        token["refresh_token"] = json!(refresh_token);
+    }
+
+    match field(&mapping.expires_in_pointer, "/expires_in") {
+        Some(Value::Number(expires_in)) => token["expires_in"] = json!(expires_in.as_u64()),
+        Some(Value::String(expires_in)) => {
+            let expires_in = expires_in.trim().parse::<u64>().map_err(|_| {
+                Error::Other(format!(
+                    "Token lifetime {:?} is not a number of seconds",
+                    expires_in
+                ))
+            })?;
+            token["expires_in"] = json!(expires_in);
+        }
+        _ => {}
+    }
+
+    if let Some(Value::String(scope)) = field(&mapping.scope_pointer, "/scope") {
+        token["scope"] = json!(scope);
+    }
+
+    Ok(token)
+}
+
+/// Describes a failed token request, which `oauth2` would describe without the cause
+pub(crate) fn describe(error: &RequestTokenError<AsyncHttpClientError, BasicErrorResponse>) -> String {
+    match error {
+        RequestTokenError::ServerResponse(response) => response.to_string(),
+        RequestTokenError::Request(Error::Other(message)) => message.clone(),
+        RequestTokenError::Request(Error::Reqwest(e)) => e.to_string(),
+        error => error.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(status_code: StatusCode, content_type: Option<&'static str>, body: &str) -> HttpResponse {
+        let mut headers = HeaderMap::new();
+        if let Some(content_type) = content_type {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+        }
+
+        HttpResponse {
+            status_code,
+            headers,
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn normalized(mapping: &TokenResponseMapping, response: HttpResponse) -> (StatusCode, Value) {
+        let response = normalize(mapping, response).unwrap();
+
+        (
+            response.status_code,
+            serde_json::from_slice(&response.body).unwrap(),
+        )
+    }
+
+    #[test]
+    fn reads_form_encoded_responses() {
+        let (status_code, body) = normalized(
+            &TokenResponseMapping::default(),
+            response(
+                StatusCode::OK,
+                Some("application/x-www-form-urlencoded; charset=utf-8"),
+                "access_token=gho_abc&scope=repo%2Cuser&token_type=bearer",
+            ),
+        );
+
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({ "access_token": "gho_abc", "token_type": "bearer", "scope": "repo,user" })
+        );
+    }
+
+    #[test]
+    fn reads_form_encoded_responses_sent_as_another_type_when_mapped() {
+        let mapping = TokenResponseMapping {
+            content_type: Some(TokenContentType::Form),
+            ..TokenResponseMapping::default()
+        };
+
+        let (_, body) = normalized(
+            &mapping,
+            response(StatusCode::OK, Some("text/plain"), "access_token=abc"),
+        );
+
+        assert_eq!(body, json!({ "access_token": "abc", "token_type": "bearer" }));
+    }
+
+    #[test]
+    fn reads_fields_the_mapping_points_to() {
+        let mapping = TokenResponseMapping {
+            access_token_pointer: Some(String::from("/data/token")),
+            refresh_token_pointer: Some(String::from("/data/refresh")),
+            expires_in_pointer: Some(String::from("/data/lifetime")),
+            scope_pointer: Some(String::from("/data/scopes")),
+            ..TokenResponseMapping::default()
+        };
+
+        let (_, body) = normalized(
+            &mapping,
+            response(
+                StatusCode::OK,
+                Some("application/json"),
+                r#"{"data":{"token":"abc","refresh":"def","lifetime":3600,"scopes":"read write"}}"#,
+            ),
+        );
+
+        assert_eq!(
+            body,
+            json!({
+                "access_token": "abc",
+                "token_type": "bearer",
+                "refresh_token": "def",
+                "expires_in": 3600,
+                "scope": "read write",
+            })
+        );
+    }
+
+    #[test]
+    fn reads_lifetimes_sent_as_strings() {
+        let (_, body) = normalized(
+            &TokenResponseMapping::default(),
+            response(
+                StatusCode::OK,
+                None,
+                r#"{"access_token":"abc","token_type":"Bearer","expires_in":" 3600"}"#,
+            ),
+        );
+
+        assert_eq!(
+            body,
+            json!({ "access_token": "abc", "token_type": "Bearer", "expires_in": 3600 })
+        );
+
+        let invalid = normalize(
+            &TokenResponseMapping::default(),
+            response(
+                StatusCode::OK,
+                None,
+                r#"{"access_token":"abc","expires_in":"an hour"}"#,
+            ),
+        );
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn reports_errors_sent_with_a_successful_status_as_bad_requests() {
+        let (status_code, body) = normalized(
+            &TokenResponseMapping::default(),
+            response(
+                StatusCode::OK,
+                Some("application/x-www-form-urlencoded"),
+                "error=bad_verification_code&error_description=The+code+is+incorrect&other=ignored",
+            ),
+        );
+
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body,
+            json!({ "error": "bad_verification_code", "error_description": "The code is incorrect" })
+        );
+    }
+
+    #[test]
+    fn keeps_the_status_of_errors() {
+        let (status_code, _) = normalized(
+            &TokenResponseMapping::default(),
+            response(
+                StatusCode::UNAUTHORIZED,
+                Some("application/json"),
+                r#"{"error":"invalid_client"}"#,
+            ),
+        );
+
+        assert_eq!(status_code, StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn rejects_responses_without_an_access_token_or_which_arent_json() {
+        let missing = normalize(
+            &TokenResponseMapping::default(),
+            response(StatusCode::OK, None, r#"{"token":"abc"}"#),
+        );
+        assert!(missing.is_err());
+
+        match normalize(
+            &TokenResponseMapping::default(),
+            response(StatusCode::OK, Some("text/html"), "<p>access_token=abc</p>"),
+        ) {
+            Err(Error::Other(message)) => assert!(!message.contains("abc")),
+            _ => panic!("expected the response to be rejected"),
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! `chappaai.dev/v2` of `OAuthConnection`, served alongside `v1` and converted to it for storage.
+//! Its spec is unchanged from `v1`, so that both resources share an API version.
+
+use super::resource::{self, OAuthConnectionStatus};
+use kube::CustomResource;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "chappaai.dev",
+    version = "v2",
+    kind = "OAuthConnection",
+    status = "OAuthConnectionStatus",
+    shortname = "oconn",
+    category = "chappaai",
+    printcolumn = r#"{"name":"API", "type":"string", "description":"OAuthApi connected to", "jsonPath":".spec.api"}"#,
+    printcolumn = r#"{"name":"Status", "type":"string", "description":"current connection status", "jsonPath":".status.phase"}"#,
+    printcolumn = r#"{"name":"Identity", "type":"string", "description":"account which authorized the connection", "jsonPath":".status.identity"}"#,
+    printcolumn = r#"{"name":"Expiry", "type":"string", "description":"token expiry", "jsonPath":".status.expiresAt"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#,
+    namespaced
+)]
+pub struct OAuthConnectionSpec {
+    #[serde(flatten)]
+    pub spec: resource::OAuthConnectionSpec,
+}
@@ -0,0 +1,22 @@
+use crate::{
+    oauth_api::api::OAuthApiWeb,
+    oauth_connection::api::{OAuthConnectionWeb, OAuthTokenWeb},
+    ErrorResponse, WEB_API_VERSION,
+};
+
+use axum::Json;
+use schemars::schema_for;
+use serde_json::{json, Value};
+
+/// JSON Schemas of the documents served by the HTTP API, for tooling to validate against
+pub async fn get() -> Json<Value> {
+    Json(json!({
+        "apiVersion": WEB_API_VERSION,
+        "definitions": {
+            "OAuthApi": schema_for!(OAuthApiWeb),
+            "OAuthConnection": schema_for!(OAuthConnectionWeb),
+            "OAuthToken": schema_for!(OAuthTokenWeb),
+            "Error": schema_for!(ErrorResponse),
+        }
+    }))
+}
@@ -0,0 +1,59 @@
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+use tokio::sync::watch;
+use tracing::info;
+
+/// Resolves once the operator has begun shutting down. Cheap to clone and await from many places.
+pub type Shutdown = Shared<BoxFuture<'static, ()>>;
+
+/// Begins shutting down everything awaiting the paired `Shutdown`
+pub struct ShutdownTrigger(watch::Sender<bool>);
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        let _ = self.0.send(true);
+    }
+}
+
+pub fn channel() -> (ShutdownTrigger, Shutdown) {
+    let (sender, mut receiver) = watch::channel(false);
+
+    let shutdown = async move {
+        while !*receiver.borrow_and_update() {
+            if receiver.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+    .boxed()
+    .shared();
+
+    (ShutdownTrigger(sender), shutdown)
+}
+
+/// Resolves on SIGTERM, as sent by the kubelet, or Ctrl+C
+pub async fn signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => futures::future::pending::<()>().await,
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = futures::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received Ctrl+C"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+}
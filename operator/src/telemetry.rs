@@ -0,0 +1,109 @@
+use crate::Result;
+use axum::{extract::MatchedPath, http::Request, middleware::Next, response::Response};
+use tracing::{field, info_span, Instrument, Span};
+use tracing_subscriber::{prelude::*, EnvFilter, Registry};
+
+/// Installs the global tracing subscriber: JSON logs, plus an OTLP exporter when built with the
+/// `opentelemetry-otlp` feature and `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+pub fn init() -> Result<()> {
+    let logger = tracing_subscriber::fmt::layer().json();
+    let env_filter = EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new("info"))?;
+
+    let collector = Registry::default().with(logger).with(env_filter);
+
+    #[cfg(feature = "opentelemetry-otlp")]
+    let collector = collector.with(otlp::layer()?);
+
+    tracing::subscriber::set_global_default(collector)?;
+
+    Ok(())
+}
+
+/// Flushes spans not yet exported. Blocks, so call it from a blocking task.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Records the OpenTelemetry trace ID on `span`'s `trace_id` field, so it is included in logs
+pub fn record_trace_id(span: &Span) {
+    #[cfg(feature = "opentelemetry-otlp")]
+    {
+        use opentelemetry::trace::TraceContextExt;
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+        let trace_id = span.context().span().span_context().trace_id();
+        span.record("trace_id", &tracing::field::display(trace_id));
+    }
+
+    #[cfg(not(feature = "opentelemetry-otlp"))]
+    let _ = span;
+}
+
+/// Middleware running each request within a span carrying its route and trace ID, which handlers'
+/// spans are nested within
+pub async fn trace_http<B>(request: Request<B>, next: Next<B>) -> Response {
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None => String::from("unmatched"),
+    };
+    let span = info_span!(
+        "http_request",
+        method = %request.method(),
+        route = %route,
+        trace_id = field::Empty
+    );
+    record_trace_id(&span);
+
+    next.run(request).instrument(span).await
+}
+
+#[cfg(feature = "opentelemetry-otlp")]
+mod otlp {
+    use crate::{Error, Result};
+    use opentelemetry::{
+        sdk::{
+            trace::{self, Sampler},
+            Resource,
+        },
+        KeyValue,
+    };
+    use opentelemetry_otlp::WithExportConfig;
+    use tracing::Subscriber;
+    use tracing_subscriber::{registry::LookupSpan, Layer};
+
+    /// Builds the OpenTelemetry layer, or none when no collector endpoint is configured.
+    ///
+    /// Configured with the standard `OTEL_EXPORTER_OTLP_ENDPOINT`, `OTEL_SERVICE_NAME` and
+    /// `OTEL_TRACES_SAMPLER_ARG` (ratio of traces to sample, between 0 and 1) environment variables.
+    pub fn layer<S>() -> Result<Option<impl Layer<S>>>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let endpoint = match std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+            Ok(endpoint) => endpoint,
+            Err(_) => return Ok(None),
+        };
+
+        let service_name = std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| String::from("chappaai"));
+
+        let ratio = match std::env::var("OTEL_TRACES_SAMPLER_ARG") {
+            Ok(ratio) => ratio
+                .parse::<f64>()
+                .map_err(|e| Error::GenericError(format!("Invalid OTEL_TRACES_SAMPLER_ARG: {}", e)))?,
+            Err(_) => 1.0,
+        };
+
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+            .with_trace_config(
+                trace::config()
+                    .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio))))
+                    .with_resource(Resource::new(vec![KeyValue::new("service.name", service_name)])),
+            )
+            .install_batch(opentelemetry::runtime::Tokio)
+            .map_err(|e| Error::GenericError(format!("Failed to install OTLP exporter: {}", e)))?;
+
+        Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+use crate::{oauth_connection::api as connections, ApplicationState};
+
+use axum::{
+    extract::{Host, Path, Query},
+    response::{Html, IntoResponse, Redirect},
+    routing::{get, post},
+    Extension, Router,
+};
+use hyper::{
+    header::{self, HeaderName},
+    HeaderMap, StatusCode,
+};
+use serde::Deserialize;
+use tracing::instrument;
+
+mod templates;
+
+/// Routes for the embedded connection UI, served alongside the JSON API
+pub fn router() -> Router {
+    Router::new()
+        .route_service("/", get(index))
+        .route_service("/ui/callback/:name", get(callback))
+        .route_service("/ui/connections/:name/disconnect", post(disconnect))
+}
+
+pub async fn index(
+    Host(host): Host,
+    headers: HeaderMap,
+    Extension(state): Extension<Arc<ApplicationState>>,
+) -> Html<String> {
+    let base_url = base_url(&host, &headers);
+    let connections = connections::web_connections(&state);
+
+    Html(templates::connections(&base_url, &connections))
+}
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+#[instrument(skip_all, fields(connection = %name))]
+pub async fn callback(
+    Query(query): Query<CallbackQuery>,
+    Path(name): Path<String>,
+    Host(host): Host,
+    headers: HeaderMap,
+    Extension(state): Extension<Arc<ApplicationState>>,
+) -> impl IntoResponse {
+    let code = match (query.code, query.error) {
+        (Some(code), _) => code,
+        (None, error) => {
+            let message = query
+                .error_description
+                .or(error)
+                .unwrap_or_else(|| String::from("No authorization code was provided"));
+
+            return Html(templates::result(&name, false, &message));
+        }
+    };
+
+    let redirect_url = callback_url(&base_url(&host, &headers), &name);
+
+    let csrf_state = query.state.unwrap_or_default();
+
+    match connections::complete_authorization(&state, name.clone(), code, csrf_state, redirect_url).await {
+        Ok(_) => Html(templates::result(&name, true, "Connected")),
+        Err(error) => Html(templates::result(&name, false, &error.to_string())),
+    }
+}
+
+/// Disconnects from the UI's form. Browsers can't send a bearer token with it, so instead only a
+/// form posted from this origin is accepted, which other sites can't forge.
+#[instrument(skip_all, fields(connection = %name))]
+pub async fn disconnect(
+    Path(name): Path<String>,
+    Host(host): Host,
+    headers: HeaderMap,
+    Extension(state): Extension<Arc<ApplicationState>>,
+) -> impl IntoResponse {
+    if !same_origin(&base_url(&host, &headers), &headers) {
+        return (
+            StatusCode::FORBIDDEN,
+            Html(templates::result(
+                &name,
+                false,
+                "Disconnecting is only allowed from this page",
+            )),
+        )
+            .into_response();
+    }
+
+    match connections::revoke_connection(&state, &name).await {
+        Ok(_) => Redirect::to("/").into_response(),
+        Err(error) => Html(templates::result(&name, false, &error.to_string())).into_response(),
+    }
+}
+
+/// The externally visible URL of the operator, honouring `X-Forwarded-Proto` from ingress controllers
+fn base_url(host: &str, headers: &HeaderMap) -> String {
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("http");
+
+    format!("{}://{}", scheme, host)
+}
+
+/// Whether the request came from a page served at `base_url`, by its `Origin`, or its `Referer`
+/// from browsers which don't send an `Origin` with forms
+fn same_origin(base_url: &str, headers: &HeaderMap) -> bool {
+    let header = |name: HeaderName| headers.get(name).and_then(|value| value.to_str().ok());
+
+    match (header(header::ORIGIN), header(header::REFERER)) {
+        (Some(origin), _) => origin == base_url,
+        (None, Some(referer)) => referer == base_url || referer.starts_with(&format!("{}/", base_url)),
+        (None, None) => false,
+    }
+}
+
+fn callback_url(base_url: &str, name: &str) -> String {
+    format!("{}/ui/callback/{}", base_url, name)
+}
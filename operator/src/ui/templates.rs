@@ -0,0 +1,104 @@
+use crate::oauth_connection::api::OAuthConnectionWeb;
+use oauth2::url::form_urlencoded;
+
+const STYLE: &str = r#"
+body { font-family: system-ui, sans-serif; margin: 2rem auto; max-width: 48rem; color: #1f2937; }
+h1 { font-size: 1.5rem; }
+.connection { display: flex; align-items: center; justify-content: space-between; padding: 0.75rem 1rem; border-bottom: 1px solid #d1d5db; }
+.phase { color: #6b7280; }
+.actions { display: flex; gap: 0.5rem; }
+.button { background: #2563eb; border: none; border-radius: 0.25rem; color: white; cursor: pointer; font-size: 0.875rem; padding: 0.375rem 0.75rem; text-decoration: none; }
+.button.secondary { background: #6b7280; }
+.ok { color: #15803d; }
+.failed { color: #b91c1c; }
+"#;
+
+fn layout(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8" />
+    <meta name="viewport" content="width=device-width, initial-scale=1" />
+    <title>{title} - Chappaai</title>
+    <style>{style}</style>
+  </head>
+  <body>
+{body}
+  </body>
+</html>
+"#,
+        title = escape(title),
+        style = STYLE,
+        body = body,
+    )
+}
+
+pub fn connections(base_url: &str, connections: &[OAuthConnectionWeb]) -> String {
+    let items = if connections.is_empty() {
+        String::from(r#"    <p class="phase">No connections found</p>"#)
+    } else {
+        connections
+            .iter()
+            .map(|connection| connection_item(base_url, connection))
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+
+    layout(
+        "Connections",
+        &format!("    <h1>Available OAuth Connections</h1>\n{}", items),
+    )
+}
+
+fn connection_item(base_url: &str, connection: &OAuthConnectionWeb) -> String {
+    let redirect_url: String =
+        form_urlencoded::byte_serialize(super::callback_url(base_url, &connection.name).as_bytes()).collect();
+
+    format!(
+        r#"    <div class="connection">
+      <div>
+        <strong>{name}</strong>
+        <div class="phase">{phase}</div>
+      </div>
+      <div class="actions">
+        <a class="button" href="/oauth/connections/{name}/connect?redirect_url={redirect_url}">Connect</a>
+        <form method="post" action="/ui/connections/{name}/disconnect">
+          <button class="button secondary" type="submit">Disconnect</button>
+        </form>
+      </div>
+    </div>"#,
+        name = escape(&connection.name),
+        phase = escape(&connection.phase),
+        redirect_url = escape(&redirect_url),
+    )
+}
+
+pub fn result(name: &str, success: bool, message: &str) -> String {
+    let (class, heading) = match success {
+        true => ("ok", "Connected"),
+        false => ("failed", "Connection failed"),
+    };
+
+    layout(
+        heading,
+        &format!(
+            r#"    <h1 class="{class}">{heading}: {name}</h1>
+    <p>{message}</p>
+    <a href="/">Back to List</a>"#,
+            class = class,
+            heading = heading,
+            name = escape(name),
+            message = escape(message),
+        ),
+    )
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
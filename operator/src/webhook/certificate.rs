@@ -0,0 +1,132 @@
+use crate::{config::Config, Error, Result};
+use k8s_openapi::{api::core::v1::Secret, ByteString};
+use kube::{
+    api::{Api, ObjectMeta, PostParams},
+    Client,
+};
+use std::collections::BTreeMap;
+use tokio_rustls::rustls::{self, ServerConfig};
+use tracing::info;
+
+/// A PEM encoded, self-signed certificate and its private key
+pub struct Certificate {
+    pub certificate: String,
+    pub private_key: String,
+}
+
+impl Certificate {
+    /// Generates a certificate valid for every name the webhook's Service is reachable by
+    fn generate(service: &str, namespace: &str) -> Result<Self> {
+        let names = vec![
+            service.to_string(),
+            format!("{}.{}", service, namespace),
+            format!("{}.{}.svc", service, namespace),
+            format!("{}.{}.svc.cluster.local", service, namespace),
+        ];
+
+        let certificate =
+            rcgen::generate_simple_self_signed(names).map_err(|e| Error::CertificateError(e.to_string()))?;
+
+        Ok(Certificate {
+            certificate: certificate
+                .serialize_pem()
+                .map_err(|e| Error::CertificateError(e.to_string()))?,
+            private_key: certificate.serialize_private_key_pem(),
+        })
+    }
+
+    fn from_secret(secret: &Secret) -> Option<Self> {
+        let data = secret.data.as_ref()?;
+        let certificate = String::from_utf8(data.get("tls.crt")?.0.clone()).ok()?;
+        let private_key = String::from_utf8(data.get("tls.key")?.0.clone()).ok()?;
+
+        Some(Certificate {
+            certificate,
+            private_key,
+        })
+    }
+
+    fn to_secret(&self, name: &str) -> Secret {
+        Secret {
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                ..ObjectMeta::default()
+            },
+            type_: Some(String::from("kubernetes.io/tls")),
+            data: Some(BTreeMap::from([
+                (
+                    String::from("tls.crt"),
+                    ByteString(self.certificate.clone().into_bytes()),
+                ),
+                (
+                    String::from("tls.key"),
+                    ByteString(self.private_key.clone().into_bytes()),
+                ),
+            ])),
+            ..Secret::default()
+        }
+    }
+
+    /// Loads the certificate shared by every replica from its Secret, generating it on first start.
+    /// Replicas race to create the Secret and the losers use the winner's, so the API server only
+    /// ever needs to trust one certificate.
+    pub async fn load_or_create(client: Client, namespace: &str, config: &Config) -> Result<Self> {
+        let secrets: Api<Secret> = Api::namespaced(client, namespace);
+
+        if let Some(certificate) = secrets
+            .get_opt(&config.webhook_secret)
+            .await?
+            .as_ref()
+            .and_then(Certificate::from_secret)
+        {
+            return Ok(certificate);
+        }
+
+        let certificate = Certificate::generate(&config.webhook_service, namespace)?;
+
+        match secrets
+            .create(
+                &PostParams::default(),
+                &certificate.to_secret(&config.webhook_secret),
+            )
+            .await
+        {
+            Ok(_) => {
+                info!(
+                    "Generated webhook certificate in Secret {}",
+                    config.webhook_secret
+                );
+                Ok(certificate)
+            }
+            Err(kube::Error::Api(response)) if response.code == 409 => {
+                Certificate::from_secret(&secrets.get(&config.webhook_secret).await?).ok_or_else(|| {
+                    Error::CertificateError(format!(
+                        "Secret {} has no tls.crt or tls.key",
+                        config.webhook_secret
+                    ))
+                })
+            }
+            Err(error) => Err(Error::KubeError(error)),
+        }
+    }
+
+    pub fn server_config(&self) -> Result<ServerConfig> {
+        let certificates = rustls_pemfile::certs(&mut self.certificate.as_bytes())
+            .map_err(|e| Error::CertificateError(e.to_string()))?
+            .into_iter()
+            .map(rustls::Certificate)
+            .collect();
+
+        let private_key = rustls_pemfile::pkcs8_private_keys(&mut self.private_key.as_bytes())
+            .map_err(|e| Error::CertificateError(e.to_string()))?
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::CertificateError(String::from("No PKCS#8 private key found")))?;
+
+        ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certificates, rustls::PrivateKey(private_key))
+            .map_err(|e| Error::CertificateError(e.to_string()))
+    }
+}
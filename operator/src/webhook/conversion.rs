@@ -0,0 +1,224 @@
+use crate::{
+    oauth_api::{self, OAuthApi},
+    oauth_connection::OAuthConnection,
+    Error, Result,
+};
+use axum::Json;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Status;
+use kube::Resource;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{instrument, warn};
+
+/// `apiextensions.k8s.io/v1` `ConversionReview`, sent by the API server to convert custom resources
+/// between the versions it serves
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversionReview {
+    pub api_version: String,
+    pub kind: String,
+    pub request: Option<ConversionRequest>,
+    pub response: Option<ConversionResponse>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversionRequest {
+    pub uid: String,
+    #[serde(rename = "desiredAPIVersion")]
+    pub desired_api_version: String,
+    pub objects: Vec<Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversionResponse {
+    pub uid: String,
+    pub converted_objects: Vec<Value>,
+    pub result: Status,
+}
+
+#[instrument(skip_all)]
+pub async fn convert(Json(review): Json<ConversionReview>) -> Json<ConversionReview> {
+    let request = match review.request {
+        Some(request) => request,
+        None => {
+            return Json(ConversionReview {
+                response: None,
+                ..review
+            })
+        }
+    };
+
+    // Objects are converted all or nothing, as the API server rejects partial results
+    let converted: Result<Vec<Value>> = request
+        .objects
+        .into_iter()
+        .map(|object| convert_object(object, &request.desired_api_version))
+        .collect();
+
+    let (converted_objects, result) = match converted {
+        Ok(objects) => (objects, Status {
+            status: Some(String::from("Success")),
+            ..Status::default()
+        }),
+        Err(error) => {
+            warn!("Failed to convert to {}: {}", request.desired_api_version, error);
+            (Vec::new(), Status {
+                status: Some(String::from("Failure")),
+                message: Some(error.to_string()),
+                ..Status::default()
+            })
+        }
+    };
+
+    Json(ConversionReview {
+        api_version: review.api_version,
+        kind: review.kind,
+        request: None,
+        response: Some(ConversionResponse {
+            uid: request.uid,
+            converted_objects,
+            result,
+        }),
+    })
+}
+
+fn convert_object(mut object: Value, desired_api_version: &str) -> Result<Value> {
+    let api_version = object["apiVersion"].as_str().unwrap_or_default().to_string();
+    let kind = object["kind"].as_str().unwrap_or_default().to_string();
+
+    if api_version == desired_api_version {
+        return Ok(object);
+    }
+
+    let v1 = OAuthApi::api_version(&());
+    let v2 = oauth_api::v2::OAuthApi::api_version(&());
+
+    match (kind.as_str(), api_version.as_str(), desired_api_version) {
+        ("OAuthApi", from, to) if from == v1 && to == v2 => {
+            let oauth_api: OAuthApi = serde_json::from_value(object).map_err(Error::SerializationError)?;
+
+            serde_json::to_value(oauth_api::v2::OAuthApi {
+                metadata: oauth_api.metadata,
+                spec: oauth_api.spec.into(),
+                status: oauth_api.status,
+            })
+            .map_err(Error::SerializationError)
+        }
+        ("OAuthApi", from, to) if from == v2 && to == v1 => {
+            let oauth_api: oauth_api::v2::OAuthApi =
+                serde_json::from_value(object).map_err(Error::SerializationError)?;
+
+            serde_json::to_value(OAuthApi {
+                metadata: oauth_api.metadata,
+                spec: oauth_api.spec.into(),
+                status: oauth_api.status,
+            })
+            .map_err(Error::SerializationError)
+        }
+        // The spec is the same in every version
+        ("OAuthConnection", from, to)
+            if [from, to]
+                .iter()
+                .all(|version| *version == OAuthConnection::api_version(&()) || *version == v2) =>
+        {
+            object["apiVersion"] = Value::from(desired_api_version);
+            Ok(object)
+        }
+        _ => Err(Error::InvalidRequest(format!(
+            "Can't convert {} from {} to {}",
+            kind, api_version, desired_api_version
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn oauth_api() -> Value {
+        json!({
+            "apiVersion": "chappaai.dev/v1",
+            "kind": "OAuthApi",
+            "metadata": { "name": "github", "namespace": "default", "resourceVersion": "42" },
+            "spec": {
+                "http": {
+                    "baseUrl": "https://api.github.com/",
+                    "headers": [{ "key": "Accept", "value": "application/json" }],
+                },
+                "auth": {
+                    "oAuth2": {
+                        "authorizationUrl": "https://github.com/login/oauth/authorize",
+                        "authorizationParams": [{ "key": "allow_signup", "value": "false" }],
+                        "defaultScopes": ["repo"],
+                        "tokenUrl": "https://github.com/login/oauth/access_token",
+                        "extraTokenParams": [{ "key": "audience", "value": "chappaai" }],
+                    },
+                },
+            },
+            "status": { "phase": "Registered" },
+        })
+    }
+
+    #[test]
+    fn round_trips_oauth_apis_through_v2() {
+        let v2 = convert_object(oauth_api(), "chappaai.dev/v2").unwrap();
+        assert_eq!(v2["apiVersion"], "chappaai.dev/v2");
+        assert_eq!(v2["metadata"]["resourceVersion"], "42");
+        assert_eq!(v2["spec"]["auth"]["type"], "OAuth2");
+        assert_eq!(v2["spec"]["http"]["headers"]["Accept"], "application/json");
+
+        let v1 = convert_object(v2.clone(), "chappaai.dev/v1").unwrap();
+        let expected =
+            serde_json::to_value(serde_json::from_value::<OAuthApi>(oauth_api()).unwrap()).unwrap();
+        assert_eq!(v1, expected);
+
+        assert_eq!(convert_object(v1, "chappaai.dev/v2").unwrap(), v2);
+    }
+
+    #[test]
+    fn relabels_oauth_connections() {
+        let oauth_connection = json!({
+            "apiVersion": "chappaai.dev/v1",
+            "kind": "OAuthConnection",
+            "metadata": { "name": "github" },
+            "spec": { "api": "github" },
+        });
+
+        let v2 = convert_object(oauth_connection.clone(), "chappaai.dev/v2").unwrap();
+
+        assert_eq!(v2["apiVersion"], "chappaai.dev/v2");
+        assert_eq!(v2["spec"], oauth_connection["spec"]);
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        assert!(convert_object(oauth_api(), "chappaai.dev/v3").is_err());
+    }
+
+    #[tokio::test]
+    async fn converts_all_objects_or_none() {
+        let review = ConversionReview {
+            api_version: String::from("apiextensions.k8s.io/v1"),
+            kind: String::from("ConversionReview"),
+            request: Some(ConversionRequest {
+                uid: String::from("705ab4f5-6393-11e8-b7cc-42010a800002"),
+                desired_api_version: String::from("chappaai.dev/v2"),
+                objects: vec![
+                    oauth_api(),
+                    json!({ "apiVersion": "chappaai.dev/v1", "kind": "Unknown" }),
+                ],
+            }),
+            response: None,
+        };
+
+        let Json(review) = convert(Json(review)).await;
+        let response = review.response.unwrap();
+
+        assert_eq!(response.uid, "705ab4f5-6393-11e8-b7cc-42010a800002");
+        assert!(response.converted_objects.is_empty());
+        assert_eq!(response.result.status.as_deref(), Some("Failure"));
+    }
+}
@@ -0,0 +1,211 @@
+//! Webhooks called by the API server: admission, which rejects invalid `OAuthApi`s and
+//! `OAuthConnection`s before they're persisted, and conversion between the CRD versions served.
+//! The API server only calls webhooks over HTTPS, so they're served on their own port with a
+//! self-signed certificate, registered in a `ValidatingWebhookConfiguration` and the CRDs.
+
+use crate::{
+    oauth_api::OAuthApi, oauth_connection::OAuthConnection, shutdown::Shutdown, telemetry, ApplicationState,
+    Error, Result,
+};
+use axum::{middleware, routing::post, Extension, Router};
+use futures::StreamExt;
+use k8s_openapi::{
+    api::admissionregistration::v1::{
+        RuleWithOperations, ServiceReference, ValidatingWebhook, ValidatingWebhookConfiguration,
+        WebhookClientConfig,
+    },
+    apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition,
+    apimachinery::pkg::apis::meta::v1::LabelSelector,
+    ByteString,
+};
+use kube::{
+    api::{Api, ObjectMeta, Patch, PatchParams},
+    CustomResourceExt,
+};
+use serde_json::json;
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+
+mod certificate;
+use certificate::Certificate;
+pub mod conversion;
+pub mod validation;
+
+/// TLS handshakes in progress at once, so that a slow client can't hold up the others
+const MAX_PENDING_HANDSHAKES: usize = 64;
+
+/// Serves the webhook until shutdown, registering it once it is listening
+pub async fn serve(state: Arc<ApplicationState>, shutdown: Shutdown) -> Result<()> {
+    let config = state.config.clone();
+    let namespace = kube::Config::infer()
+        .await
+        .map_err(|e| Error::GenericError(format!("Failed to infer namespace: {}", e)))?
+        .default_namespace;
+
+    let certificate = Certificate::load_or_create(state.client.clone(), &namespace, &config).await?;
+    let acceptor = TlsAcceptor::from(Arc::new(certificate.server_config()?));
+
+    let listener = TcpListener::bind(config.webhook_bind_address)
+        .await
+        .map_err(|e| Error::GenericError(format!("Failed to bind webhook: {}", e)))?;
+
+    register(&state, &namespace, &certificate).await?;
+    register_conversion::<OAuthApi>(&state, &namespace, &certificate).await?;
+    register_conversion::<OAuthConnection>(&state, &namespace, &certificate).await?;
+    info!("Serving webhooks on {}", config.webhook_bind_address);
+
+    let incoming = futures::stream::unfold(listener, |listener| async move {
+        Some((listener.accept().await, listener))
+    })
+    .filter_map(|accepted| async move {
+        accepted
+            .map_err(|e| warn!("Failed to accept webhook connection: {}", e))
+            .ok()
+    })
+    .map(move |(stream, _)| acceptor.accept(stream))
+    .buffer_unordered(MAX_PENDING_HANDSHAKES)
+    .filter_map(|handshake| async move {
+        match handshake {
+            Ok(stream) => Some(Ok::<_, std::io::Error>(stream)),
+            Err(error) => {
+                warn!("Webhook TLS handshake failed: {}", error);
+                None
+            }
+        }
+    });
+
+    let router = Router::new()
+        .route_service("/validate/oauthapis", post(validation::oauth_apis))
+        .route_service("/validate/oauthconnections", post(validation::oauth_connections))
+        .route_service("/convert", post(conversion::convert))
+        .layer(middleware::from_fn(telemetry::trace_http))
+        .layer(Extension(state));
+
+    hyper::Server::builder(hyper::server::accept::from_stream(incoming))
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(shutdown)
+        .await
+        .map_err(Error::HyperError)
+}
+
+/// Applies the `ValidatingWebhookConfiguration`, scoped to the operator's namespace as that is the
+/// only one it watches. Every replica applies the same configuration, as they share a certificate.
+async fn register(state: &ApplicationState, namespace: &str, certificate: &Certificate) -> Result<()> {
+    let name = format!("chappaai-{}", namespace);
+
+    let webhook = |resource: &str| ValidatingWebhook {
+        name: format!("{}.chappaai.dev", resource),
+        admission_review_versions: vec![String::from("v1")],
+        client_config: client_config(state, namespace, certificate, &format!("/validate/{}", resource)),
+        failure_policy: Some(String::from("Fail")),
+        // Objects are validated as v1, whichever version they're written in
+        match_policy: Some(String::from("Equivalent")),
+        namespace_selector: Some(LabelSelector {
+            match_labels: Some(BTreeMap::from([(
+                String::from("kubernetes.io/metadata.name"),
+                namespace.to_string(),
+            )])),
+            ..LabelSelector::default()
+        }),
+        rules: Some(vec![RuleWithOperations {
+            api_groups: Some(vec![String::from("chappaai.dev")]),
+            api_versions: Some(vec![String::from("v1")]),
+            operations: Some(vec![String::from("CREATE"), String::from("UPDATE")]),
+            resources: Some(vec![resource.to_string()]),
+            scope: Some(String::from("Namespaced")),
+        }]),
+        side_effects: String::from("None"),
+        timeout_seconds: Some(5),
+        ..ValidatingWebhook::default()
+    };
+
+    let configuration = ValidatingWebhookConfiguration {
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            ..ObjectMeta::default()
+        },
+        webhooks: Some(vec![webhook("oauthapis"), webhook("oauthconnections")]),
+    };
+
+    Api::<ValidatingWebhookConfiguration>::all(state.client.clone())
+        .patch(
+            &name,
+            &PatchParams::apply(&state.config.field_manager).force(),
+            &Patch::Apply(&configuration),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Points the CRD's conversion at the webhook, and serves the versions which need it. The CRDs are
+/// installed without either, so that `v1`, which is stored, stays readable should the operator
+/// never run, and no version is served which can't be converted.
+async fn register_conversion<K>(
+    state: &ApplicationState,
+    namespace: &str,
+    certificate: &Certificate,
+) -> Result<()>
+where
+    K: CustomResourceExt,
+{
+    let crds = Api::<CustomResourceDefinition>::all(state.client.clone());
+
+    // Versions are applied as a whole, so every version is included, now served
+    let versions = crds
+        .get(K::crd_name())
+        .await?
+        .spec
+        .versions
+        .into_iter()
+        .map(|mut version| {
+            version.served = true;
+            version
+        })
+        .collect::<Vec<_>>();
+
+    // The client configuration is shared with admission webhooks, though typed separately
+    let patch = json!({
+        "apiVersion": "apiextensions.k8s.io/v1",
+        "kind": "CustomResourceDefinition",
+        "spec": {
+            "conversion": {
+                "strategy": "Webhook",
+                "webhook": {
+                    "clientConfig": client_config(state, namespace, certificate, "/convert"),
+                    "conversionReviewVersions": ["v1"],
+                },
+            },
+            "versions": versions,
+        }
+    });
+
+    crds.patch(
+        K::crd_name(),
+        &PatchParams::apply(&state.config.field_manager).force(),
+        &Patch::Apply(&patch),
+    )
+    .await?;
+
+    Ok(())
+}
+
+fn client_config(
+    state: &ApplicationState,
+    namespace: &str,
+    certificate: &Certificate,
+    path: &str,
+) -> WebhookClientConfig {
+    WebhookClientConfig {
+        ca_bundle: Some(ByteString(certificate.certificate.clone().into_bytes())),
+        service: Some(ServiceReference {
+            name: state.config.webhook_service.clone(),
+            namespace: namespace.to_string(),
+            path: Some(path.to_string()),
+            port: Some(443),
+        }),
+        url: None,
+    }
+}
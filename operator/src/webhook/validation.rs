@@ -0,0 +1,199 @@
+use crate::{
+    oauth_api::OAuthApi,
+    oauth_connection::{self, OAuthConnection},
+    ApplicationState, Error, Result,
+};
+use axum::{Extension, Json};
+use kube::{
+    api::{Api, DynamicObject},
+    core::admission::{AdmissionRequest, AdmissionResponse, AdmissionReview},
+    ResourceExt,
+};
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+use tracing::{info, instrument};
+
+#[instrument(skip_all)]
+pub async fn oauth_apis(
+    Extension(_state): Extension<Arc<ApplicationState>>,
+    Json(review): Json<AdmissionReview<DynamicObject>>,
+) -> Json<AdmissionReview<DynamicObject>> {
+    review_with(
+        review,
+        |oauth_api: OAuthApi, _| async move { oauth_api.validate() },
+    )
+    .await
+}
+
+#[instrument(skip_all)]
+pub async fn oauth_connections(
+    Extension(state): Extension<Arc<ApplicationState>>,
+    Json(review): Json<AdmissionReview<DynamicObject>>,
+) -> Json<AdmissionReview<DynamicObject>> {
+    review_with(
+        review,
+        |oauth_connection: OAuthConnection, namespace| async move {
+            oauth_connection.validate(&state.config)?;
+            oauth_connection::check_target_namespaces(&state.client, &oauth_connection).await?;
+
+            // Checked against the API server, rather than the store, so that an OAuthApi applied
+            // alongside its connections is found
+            let oauth_apis: Api<OAuthApi> = Api::namespaced(state.client.clone(), &namespace);
+            match oauth_apis.get_opt(&oauth_connection.spec.api).await? {
+                Some(oauth_api) if oauth_connection.requested_scopes(&oauth_api).is_empty() => {
+                    Err(Error::InvalidSpec(format!(
+                        "scopes are required, as OAuthApi {} has no default or required scopes",
+                        oauth_connection.spec.api
+                    )))
+                }
+                Some(_) => Ok(()),
+                None => Err(Error::InvalidSpec(format!(
+                    "OAuthApi {} does not exist in namespace {}",
+                    oauth_connection.spec.api, namespace
+                ))),
+            }
+        },
+    )
+    .await
+}
+
+/// Admits the request if `validate` accepts its object. Updates which leave the spec untouched,
+/// such as adding a finalizer, are always admitted, so existing objects are never stuck.
+async fn review_with<K, F, Fut>(
+    review: AdmissionReview<DynamicObject>,
+    validate: F,
+) -> Json<AdmissionReview<DynamicObject>>
+where
+    K: DeserializeOwned,
+    F: FnOnce(K, String) -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    let request: AdmissionRequest<DynamicObject> = match review.try_into() {
+        Ok(request) => request,
+        Err(error) => return Json(AdmissionResponse::invalid(error.to_string()).into_review()),
+    };
+
+    let response = AdmissionResponse::from(&request);
+
+    let mut object = match request.object {
+        Some(object) => object,
+        None => return Json(response.into_review()),
+    };
+
+    if let Some(old_object) = &request.old_object {
+        if old_object.data.get("spec") == object.data.get("spec") {
+            return Json(response.into_review());
+        }
+    }
+
+    let namespace = request
+        .namespace
+        .clone()
+        .or_else(|| object.namespace())
+        .unwrap_or_default();
+    object.metadata.namespace = Some(namespace.clone());
+
+    let result = match serde_json::to_value(&object).and_then(serde_json::from_value::<K>) {
+        Ok(object) => validate(object, namespace).await,
+        Err(error) => Err(Error::InvalidSpec(error.to_string())),
+    };
+
+    match result {
+        Ok(()) => Json(response.into_review()),
+        Err(error) => {
+            info!("Denied {:?} of {}: {}", request.operation, request.name, error);
+            Json(response.deny(error.to_string()).into_review())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn oauth_api(base_url: &str) -> serde_json::Value {
+        json!({
+            "apiVersion": "chappaai.dev/v1",
+            "kind": "OAuthApi",
+            "metadata": { "name": "github" },
+            "spec": {
+                "http": { "baseUrl": base_url },
+                "auth": {
+                    "oAuth2": {
+                        "authorizationUrl": "https://github.com/login/oauth/authorize",
+                        "tokenUrl": "https://github.com/login/oauth/access_token",
+                    },
+                },
+            },
+        })
+    }
+
+    fn review(
+        operation: &str,
+        object: serde_json::Value,
+        old_object: serde_json::Value,
+    ) -> AdmissionReview<DynamicObject> {
+        serde_json::from_value(json!({
+            "apiVersion": "admission.k8s.io/v1",
+            "kind": "AdmissionReview",
+            "request": {
+                "uid": "705ab4f5-6393-11e8-b7cc-42010a800002",
+                "kind": { "group": "chappaai.dev", "version": "v1", "kind": "OAuthApi" },
+                "resource": { "group": "chappaai.dev", "version": "v1", "resource": "oauthapis" },
+                "name": "github",
+                "namespace": "default",
+                "operation": operation,
+                "userInfo": { "username": "admin" },
+                "object": object,
+                "oldObject": old_object,
+                "dryRun": false,
+            },
+        }))
+        .unwrap()
+    }
+
+    /// Whether the request was admitted, and the namespace the object was validated in
+    async fn admitted(review: AdmissionReview<DynamicObject>) -> (bool, Option<String>) {
+        let mut validated_namespace = None;
+        let Json(review) = review_with(review, |oauth_api: OAuthApi, namespace| {
+            validated_namespace = Some(namespace);
+            async move { oauth_api.validate() }
+        })
+        .await;
+
+        (review.response.unwrap().allowed, validated_namespace)
+    }
+
+    #[tokio::test]
+    async fn admits_valid_objects_in_the_requests_namespace() {
+        let review = review("CREATE", oauth_api("https://api.github.com/"), json!(null));
+
+        assert_eq!(admitted(review).await, (true, Some(String::from("default"))));
+    }
+
+    #[tokio::test]
+    async fn denies_invalid_objects() {
+        let review = review("CREATE", oauth_api("ftp://api.github.com/"), json!(null));
+
+        assert!(!admitted(review).await.0);
+    }
+
+    #[tokio::test]
+    async fn admits_updates_which_leave_the_spec_untouched() {
+        let mut object = oauth_api("ftp://api.github.com/");
+        object["metadata"]["finalizers"] = json!(["chappaai.dev/secret-targets"]);
+        let review = review("UPDATE", object, oauth_api("ftp://api.github.com/"));
+
+        assert_eq!(admitted(review).await, (true, None));
+    }
+
+    #[tokio::test]
+    async fn denies_objects_which_do_not_deserialize() {
+        let mut object = oauth_api("https://api.github.com/");
+        object["spec"]["http"] = json!("https://api.github.com/");
+        let review = review("CREATE", object, json!(null));
+
+        assert_eq!(admitted(review).await, (false, None));
+    }
+}